@@ -0,0 +1,641 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use crate::emulator::InstructionSet;
+use crate::opcodes::{self, OpcodeInfo, OPCODES, Operand};
+
+
+const MAX_NESTING_DEPTH: usize = 16;
+
+#[derive(Error, Debug)]
+#[error("{file}:{line}:{column}: {message}")]
+pub struct AssemblerError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+struct Location {
+    file: String,
+    line: usize,
+    column: usize,
+}
+
+// A value written in the source, such as `0x20`, `label` or `label+2`, kept as text until every label is known
+#[derive(Debug, Clone)]
+struct Expression {
+    text: String,
+    location: Location,
+}
+
+// A macro body is kept as source lines and assembled again, with the parameters replaced, wherever it is used
+#[derive(Debug)]
+struct Macro {
+    parameters: Vec<String>,
+    file: PathBuf,
+    line: usize,
+    body: Vec<(usize, String)>,
+}
+
+#[derive(Debug)]
+enum Statement {
+    Instruction(&'static OpcodeInfo, Vec<Option<Expression>>),
+    Bytes(Vec<Expression>),
+    Words(Vec<Expression>),
+    Raw(Vec<u8>),
+}
+
+/// Assembles Cowgod style CHIP-8 assembly, the same syntax produced by the disassembler.
+///
+/// Instructions are written as `LD V1, 0x20`. Labels are declared with `: name` or `name:`, constants with
+/// `:const NAME value`, macros with `:macro name parameters {` up to a closing `}` line, data with `:byte`, `:word`
+/// and `:sprite`, and other files are pulled in with `:include "file"`. Comments start with `#` or `;`. Numbers can
+/// be decimal, hexadecimal (`0x`) or binary (`0b`), and can be combined with labels and constants using `+` and `-`.
+///
+/// Labels and constants declared in a macro body are local to each expansion, so a macro with a loop can be used more
+/// than once. They can only be referred to from within the same body.
+pub struct Assembler {
+    instruction_set: InstructionSet,
+    origin: u16,
+    address: u32,
+    symbols: HashMap<String, u32>,
+    statements: Vec<(u32, Location, Statement)>,
+    include_stack: Vec<PathBuf>,
+    macros: HashMap<String, Macro>,
+    // The macro whose body is being read, if any
+    recording: Option<(String, Macro)>,
+    expansion_depth: usize,
+    // The number of macro expansions so far, which tells apart the local labels of each
+    expansions: usize,
+}
+
+impl Assembler {
+    pub fn new(instruction_set: InstructionSet, origin: u16) -> Assembler {
+        Assembler {
+            instruction_set,
+            origin,
+            address: origin as u32,
+            symbols: HashMap::new(),
+            statements: Vec::new(),
+            include_stack: Vec::new(),
+            macros: HashMap::new(),
+            recording: None,
+            expansion_depth: 0,
+            expansions: 0,
+        }
+    }
+
+    /// Assembles a source file, resolving includes relative to it
+    pub fn assemble_file(self, path: &Path) -> Result<Vec<u8>, AssemblerError> {
+        let source = fs::read_to_string(path).map_err(|e| AssemblerError {
+            file: path.display().to_string(),
+            line: 0,
+            column: 0,
+            message: format!("Cannot read file: {}", e),
+        })?;
+        self.assemble_named(&source, path)
+    }
+
+    /// Assembles source text, resolving includes relative to the current directory
+    pub fn assemble(self, source: &str) -> Result<Vec<u8>, AssemblerError> {
+        self.assemble_named(source, Path::new("<input>"))
+    }
+
+    fn assemble_named(mut self, source: &str, path: &Path) -> Result<Vec<u8>, AssemblerError> {
+        self.parse_source(source, path)?;
+
+        let mut output = Vec::new();
+        let statements = std::mem::take(&mut self.statements);
+        for (address, location, statement) in statements.iter() {
+            let offset = (*address - self.origin as u32) as usize;
+            output.resize(offset, 0);
+            match statement {
+                Statement::Instruction(info, operands) => {
+                    let mut opcode = info.pattern;
+                    let mut next_word = None;
+                    for (operand, expression) in info.operands.iter().zip(operands) {
+                        let expression = match expression {
+                            Some(expression) => expression,
+                            None => continue,
+                        };
+                        let value = self.evaluate(expression)?;
+                        if *operand == Operand::LongAddress {
+                            next_word = Some(self.fit(value, 0xFFFF, expression)?);
+                            continue;
+                        }
+                        opcode = OpcodeInfo::encode_operand(*operand, opcode, self.fit(value, 0xFFFF, expression)?)
+                            .ok_or_else(|| error(&expression.location, format!("Value {:#x} does not fit in the operand", value)))?;
+                    }
+                    // The emulator decodes with the same table, so anything assembled here runs as written
+                    debug_assert_eq!(opcodes::decode(opcode, self.instruction_set), Some(*info));
+                    output.extend_from_slice(&opcode.to_be_bytes());
+                    if let Some(word) = next_word {
+                        output.extend_from_slice(&word.to_be_bytes());
+                    }
+                }
+                Statement::Bytes(expressions) => {
+                    for expression in expressions {
+                        let value = self.evaluate(expression)?;
+                        output.push(self.fit(value, 0xFF, expression)? as u8);
+                    }
+                }
+                Statement::Words(expressions) => {
+                    for expression in expressions {
+                        let value = self.evaluate(expression)?;
+                        output.extend_from_slice(&self.fit(value, 0xFFFF, expression)?.to_be_bytes());
+                    }
+                }
+                Statement::Raw(bytes) => output.extend_from_slice(bytes),
+            }
+            if output.len() + self.origin as usize > 0x10000 {
+                return Err(error(location, "Program does not fit in memory".to_string()));
+            }
+        }
+        Ok(output)
+    }
+
+    fn parse_source(&mut self, source: &str, path: &Path) -> Result<(), AssemblerError> {
+        self.include_stack.push(path.to_path_buf());
+        for (number, line) in source.lines().enumerate() {
+            let line = strip_comment(line);
+            if self.recording.is_some() {
+                if line.trim() == "}" {
+                    let (name, definition) = self.recording.take().unwrap();
+                    self.macros.insert(name, definition);
+                } else if let Some((_, definition)) = &mut self.recording {
+                    definition.body.push((number + 1, line.to_string()));
+                }
+                continue;
+            }
+            self.parse_line(line, path, number + 1)?;
+        }
+        if let Some((name, definition)) = self.recording.take() {
+            let location = Location { file: path.display().to_string(), line: definition.line, column: 1 };
+            return Err(error(&location, format!("Macro '{}' is never closed with '}}'", name)));
+        }
+        self.include_stack.pop();
+        Ok(())
+    }
+
+    fn parse_line(&mut self, line: &str, path: &Path, line_number: usize) -> Result<(), AssemblerError> {
+        let file = path.display().to_string();
+        let location = |column: usize| Location { file: file.clone(), line: line_number, column: column + 1 };
+        let mut tokens = tokenize(line);
+        if tokens.is_empty() {
+            return Ok(());
+        }
+
+        // Labels, either `: name` or `name:`, can be followed by a statement on the same line
+        if tokens[0].0 == ":" {
+            let (name, column) = *tokens.get(1).ok_or_else(|| error(&location(tokens[0].1), "Expected a label name".to_string()))?;
+            self.define(name, self.address, &location(column))?;
+            tokens.drain(..2);
+        } else if let Some(name) = tokens[0].0.strip_suffix(':').filter(|name| !name.starts_with(':')) {
+            self.define(name, self.address, &location(tokens[0].1))?;
+            tokens.remove(0);
+        }
+        let (keyword, column) = match tokens.first() {
+            Some(token) => *token,
+            None => return Ok(()),
+        };
+        let arguments: Vec<(&str, usize)> = tokens[1..].to_vec();
+        let expression = |(text, column): (&str, usize)| Expression { text: text.to_string(), location: location(column) };
+
+        match keyword.to_lowercase().as_str() {
+            ":const" => {
+                if arguments.len() != 2 {
+                    return Err(error(&location(column), "Expected :const NAME value".to_string()));
+                }
+                let value = self.evaluate(&expression(arguments[1]))?;
+                self.define(arguments[0].0, value, &location(arguments[0].1))
+            }
+            ":byte" => {
+                let expressions: Vec<Expression> = arguments.into_iter().map(expression).collect();
+                self.push(location(column), expressions.len(), Statement::Bytes(expressions))
+            }
+            ":word" => {
+                let expressions: Vec<Expression> = arguments.into_iter().map(expression).collect();
+                self.push(location(column), expressions.len() * 2, Statement::Words(expressions))
+            }
+            ":sprite" => {
+                // Each row is written as 8 or 16 pixels, where `1` or `X` is set and `0` or `.` is clear
+                let mut bytes = Vec::new();
+                for (row, column) in arguments {
+                    if !matches!(row.len(), 8 | 16) || !row.chars().all(|pixel| "01.Xx".contains(pixel)) {
+                        return Err(error(&location(column), format!("Invalid sprite row '{}', expected 8 or 16 of 0, 1, . or X", row)));
+                    }
+                    let bits = row.chars().fold(0u16, |bits, pixel| bits << 1 | matches!(pixel, '1' | 'X' | 'x') as u16);
+                    if row.len() == 16 {
+                        bytes.push((bits >> 8) as u8);
+                    }
+                    bytes.push(bits as u8);
+                }
+                self.push(location(column), bytes.len(), Statement::Raw(bytes))
+            }
+            ":include" => {
+                let name = arguments.first()
+                    .and_then(|(name, _)| name.strip_prefix('"').and_then(|name| name.strip_suffix('"')))
+                    .ok_or_else(|| error(&location(column), "Expected :include \"file\"".to_string()))?;
+                let included = path.parent().unwrap_or_else(|| Path::new("")).join(name);
+                if self.include_stack.len() >= MAX_NESTING_DEPTH || self.include_stack.contains(&included) {
+                    return Err(error(&location(arguments[0].1), format!("'{}' is included recursively", name)));
+                }
+                let source = fs::read_to_string(&included)
+                    .map_err(|e| error(&location(arguments[0].1), format!("Cannot include '{}': {}", name, e)))?;
+                self.parse_source(&source, &included)
+            }
+            ":macro" => {
+                if arguments.len() < 2 || arguments[arguments.len() - 1].0 != "{" {
+                    return Err(error(&location(column), "Expected :macro name parameters {".to_string()));
+                }
+                let (name, name_column) = arguments[0];
+                if self.macros.contains_key(name) || name.starts_with(':') {
+                    return Err(error(&location(name_column), format!("Invalid macro name '{}'", name)));
+                }
+                let parameters = arguments[1..arguments.len() - 1].iter().map(|(text, _)| text.to_string()).collect();
+                let definition = Macro { parameters, file: path.to_path_buf(), line: line_number, body: Vec::new() };
+                self.recording = Some((name.to_string(), definition));
+                Ok(())
+            }
+            _ if self.macros.contains_key(keyword) => self.expand(keyword, &arguments, &location(column)),
+            directive if directive.starts_with(':') => {
+                Err(error(&location(column), format!("Unknown directive '{}'", keyword)))
+            }
+            _ => {
+                let operands = split_operands(line, &tokens);
+                let instruction = |instruction_set| self.match_instruction(keyword, &operands, instruction_set, &location);
+                let (info, values) = match instruction(self.instruction_set) {
+                    Some(instruction) => instruction,
+                    None => {
+                        let message = match instruction(InstructionSet::XoChip) {
+                            Some((info, _)) => format!("'{}' requires the {:?} instruction set", line.trim(), info.instruction_set),
+                            None => format!("Invalid instruction '{}'", line.trim()),
+                        };
+                        return Err(error(&location(column), message));
+                    }
+                };
+                self.push(location(column), info.size() as usize, Statement::Instruction(info, values))
+            }
+        }
+    }
+
+    // Finds the opcode table entry matching a mnemonic and its operands. Operands that hold values are returned
+    // so they can be evaluated once every label is known
+    fn match_instruction(&self, mnemonic: &str, operands: &[(&str, usize)], instruction_set: InstructionSet,
+                         location: &dyn Fn(usize) -> Location) -> Option<(&'static OpcodeInfo, Vec<Option<Expression>>)> {
+        OPCODES.iter()
+            .filter(|info| info.mnemonic.eq_ignore_ascii_case(mnemonic) && info.operands.len() == operands.len())
+            .filter(|info| info.instruction_set <= instruction_set)
+            .find_map(|info| {
+                let mut values = Vec::new();
+                for (operand, &(text, column)) in info.operands.iter().zip(operands) {
+                    let value = |text: &str, column: usize| Some(Expression { text: text.to_string(), location: location(column) });
+                    match operand {
+                        Operand::Vx | Operand::Vy => values.push(value(&parse_register(text)?.to_string(), column)),
+                        Operand::Keyword(keyword) => {
+                            if !keyword.eq_ignore_ascii_case(text) {
+                                return None;
+                            }
+                            values.push(None);
+                        }
+                        // `long` selects the XO-CHIP form that takes a 16-bit address
+                        Operand::LongAddress => {
+                            let address = strip_long(text)?;
+                            values.push(value(address, column + text.len() - address.len()));
+                        }
+                        Operand::Address if strip_long(text).is_some() => return None,
+                        _ => {
+                            if parse_register(text).is_some() || self.is_keyword(text) {
+                                return None;
+                            }
+                            values.push(value(text, column));
+                        }
+                    }
+                }
+                Some((info, values))
+            })
+    }
+
+    fn expand(&mut self, name: &str, arguments: &[(&str, usize)], location: &Location) -> Result<(), AssemblerError> {
+        let definition = &self.macros[name];
+        if arguments.len() != definition.parameters.len() {
+            return Err(error(location, format!("Macro '{}' expects {} arguments", name, definition.parameters.len())));
+        }
+        if self.expansion_depth >= MAX_NESTING_DEPTH {
+            return Err(error(location, format!("Macro '{}' expands recursively", name)));
+        }
+        // Parameters are replaced by the arguments, and local labels and constants by names only this expansion uses,
+        // which can't be written in the source
+        self.expansions += 1;
+        let mut replacements: Vec<(&str, String)> = definition.parameters.iter().map(String::as_str)
+            .zip(arguments.iter().map(|(argument, _)| argument.to_string()))
+            .collect();
+        for (_, line) in definition.body.iter() {
+            let tokens = tokenize(line);
+            let mut names = Vec::new();
+            let mut statement = &tokens[..];
+            if let Some((":", _)) = statement.first() {
+                names.extend(statement.get(1).map(|(name, _)| *name));
+                statement = statement.get(2..).unwrap_or_default();
+            } else if let Some(label) = statement.first().and_then(|(token, _)| token.strip_suffix(':')) {
+                if !label.starts_with(':') {
+                    names.push(label);
+                    statement = &statement[1..];
+                }
+            }
+            if statement.first().is_some_and(|(keyword, _)| keyword.eq_ignore_ascii_case(":const")) {
+                names.extend(statement.get(1).map(|(name, _)| *name));
+            }
+            for local in names {
+                if replacements.iter().all(|(name, _)| *name != local) {
+                    replacements.push((local, format!("{}@{}", local, self.expansions)));
+                }
+            }
+        }
+        let body: Vec<(usize, String)> = definition.body.iter()
+            .map(|(number, line)| (*number, substitute(line, &replacements)))
+            .collect();
+        let file = definition.file.clone();
+        self.expansion_depth += 1;
+        for (number, line) in body {
+            self.parse_line(&line, &file, number)?;
+        }
+        self.expansion_depth -= 1;
+        Ok(())
+    }
+
+    fn is_keyword(&self, text: &str) -> bool {
+        OPCODES.iter().flat_map(|info| info.operands.iter()).any(|operand| match operand {
+            Operand::Keyword(keyword) => keyword.eq_ignore_ascii_case(text),
+            _ => false,
+        })
+    }
+
+    fn push(&mut self, location: Location, size: usize, statement: Statement) -> Result<(), AssemblerError> {
+        self.statements.push((self.address, location, statement));
+        self.address += size as u32;
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, value: u32, location: &Location) -> Result<(), AssemblerError> {
+        // Local labels and constants of macros are defined with the number of their expansion, see `expand`
+        let name_part = match self.expansion_depth {
+            0 => name,
+            _ => name.split_once('@').map_or(name, |(name, _)| name),
+        };
+        let valid = name_part.chars().next().is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+            && name_part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && parse_register(name_part).is_none()
+            && !self.is_keyword(name_part);
+        if !valid {
+            return Err(error(location, format!("Invalid name '{}'", name)));
+        }
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(error(location, format!("'{}' is already defined", name)));
+        }
+        Ok(())
+    }
+
+    // Evaluates a sum of numbers, labels and constants such as `label+2` or `0x10-1`
+    fn evaluate(&self, expression: &Expression) -> Result<u32, AssemblerError> {
+        let mut total: i64 = 0;
+        let mut sign = 1;
+        let mut term = String::new();
+        for c in expression.text.chars().chain(std::iter::once('+')) {
+            if (c == '+' || c == '-') && !term.is_empty() {
+                total += sign * self.evaluate_term(&term, expression)? as i64;
+                term.clear();
+                sign = if c == '-' { -1 } else { 1 };
+            } else if c == '-' && term.is_empty() {
+                sign = -sign;
+            } else if !c.is_whitespace() {
+                term.push(c);
+            }
+        }
+        if total < 0 {
+            return Err(error(&expression.location, format!("'{}' is negative", expression.text)));
+        }
+        Ok(total as u32)
+    }
+
+    fn evaluate_term(&self, term: &str, expression: &Expression) -> Result<u32, AssemblerError> {
+        let number = if let Some(hex) = term.strip_prefix("0x").or_else(|| term.strip_prefix("0X")) {
+            u32::from_str_radix(hex, 16).ok()
+        } else if let Some(binary) = term.strip_prefix("0b").or_else(|| term.strip_prefix("0B")) {
+            u32::from_str_radix(binary, 2).ok()
+        } else if term.starts_with(|c: char| c.is_ascii_digit()) {
+            term.parse().ok()
+        } else {
+            return self.symbols.get(term).copied()
+                .ok_or_else(|| error(&expression.location, format!("'{}' is not defined", term)));
+        };
+        number.ok_or_else(|| error(&expression.location, format!("Invalid number '{}'", term)))
+    }
+
+    fn fit(&self, value: u32, max: u32, expression: &Expression) -> Result<u16, AssemblerError> {
+        if value > max {
+            return Err(error(&expression.location, format!("Value {:#x} of '{}' is too large", value, expression.text)));
+        }
+        Ok(value as u16)
+    }
+}
+
+fn error(location: &Location, message: String) -> AssemblerError {
+    AssemblerError {
+        file: location.file.clone(),
+        line: location.line,
+        column: location.column,
+        message,
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' | ';' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+// Splits a line into whitespace separated tokens with their starting columns. Commas are treated as whitespace
+fn tokenize(line: &str) -> Vec<(&str, usize)> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in line.char_indices().chain(std::iter::once((line.len(), ' '))) {
+        let separator = c.is_whitespace() || c == ',';
+        match start {
+            Some(token_start) if separator => {
+                tokens.push((&line[token_start..i], token_start));
+                start = None;
+            }
+            None if !separator => start = Some(i),
+            _ => {}
+        }
+    }
+    tokens
+}
+
+// Instruction operands are separated by commas and may contain spaces, as in `LD I, label + 2`
+fn split_operands<'a>(line: &'a str, tokens: &[(&str, usize)]) -> Vec<(&'a str, usize)> {
+    let mnemonic_end = tokens[0].1 + tokens[0].0.len();
+    let rest = &line[mnemonic_end..];
+    if rest.trim().is_empty() {
+        return Vec::new();
+    }
+    let mut operands = Vec::new();
+    let mut offset = mnemonic_end;
+    for part in rest.split(',') {
+        let trimmed = part.trim_start();
+        let column = offset + part.len() - trimmed.len();
+        operands.push((trimmed.trim_end(), column));
+        offset += part.len() + 1;
+    }
+    operands
+}
+
+// Replaces every name in a macro body line that has a replacement, such as a parameter with its argument
+fn substitute(line: &str, replacements: &[(&str, String)]) -> String {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut output = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find(is_name) {
+        output.push_str(&rest[..start]);
+        let end = rest[start..].find(|c| !is_name(c)).map_or(rest.len(), |end| start + end);
+        let name = &rest[start..end];
+        match replacements.iter().find(|(replaced, _)| *replaced == name) {
+            Some((_, replacement)) => output.push_str(replacement),
+            None => output.push_str(name),
+        }
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+    output
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let register = text.strip_prefix('V').or_else(|| text.strip_prefix('v'))?;
+    if register.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(register, 16).ok()
+}
+
+fn strip_long(text: &str) -> Option<&str> {
+    let (keyword, rest) = text.split_once(char::is_whitespace)?;
+    keyword.eq_ignore_ascii_case("long").then(|| rest.trim_start())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::Disassembler;
+
+    fn assemble(source: &str) -> Result<Vec<u8>, AssemblerError> {
+        Assembler::new(InstructionSet::XoChip, 0x200).assemble(source)
+    }
+
+    #[test]
+    fn disassembled_roms_assemble_to_the_same_bytes() {
+        let roms: [&[u8]; 5] = [
+            include_bytes!("../roms/IBM Logo.ch8"),
+            include_bytes!("../roms/test_opcode.ch8"),
+            include_bytes!("../roms/Brix [Andreas Gustafsson, 1990].ch8"),
+            include_bytes!("../roms/Space Invaders [David Winter].ch8"),
+            include_bytes!("../roms/Tetris [Fran Dachille, 1991].ch8"),
+        ];
+        for rom in roms {
+            let listing = Disassembler::new(rom, 0x200, InstructionSet::SuperChip).disassemble();
+            let assembled = Assembler::new(InstructionSet::SuperChip, 0x200).assemble(&listing).unwrap();
+            assert_eq!(assembled, rom);
+        }
+    }
+
+    #[test]
+    fn resolves_labels_and_constants() {
+        let source = "
+            :const SPEED 3
+            start:
+                LD V0, SPEED + 1   # forward references are resolved once every label is known
+                JP end
+            : table
+                :byte 1 2 0b11
+            end:
+                LD I, table - 1
+                LD I, long end
+                :sprite X.X.X.X. ..XXXX..
+                :word start
+        ";
+        assert_eq!(assemble(source).unwrap(), [
+            0x60, 0x04, 0x12, 0x07, 0x01, 0x02, 0x03, 0xA2, 0x03, 0xF0, 0x00, 0x02, 0x07, 0xAA, 0x3C, 0x02, 0x00,
+        ]);
+    }
+
+    #[test]
+    fn expands_macros_with_local_labels() {
+        let source = "
+            :macro wait register {
+                LD register, 10
+            loop:
+                ADD register, -1 + 0x100
+                SE register, 0
+                JP loop
+            }
+            wait V1
+            wait V2
+        ";
+        assert_eq!(assemble(source).unwrap(), [
+            0x61, 0x0A, 0x71, 0xFF, 0x31, 0x00, 0x12, 0x02,
+            0x62, 0x0A, 0x72, 0xFF, 0x32, 0x00, 0x12, 0x0A,
+        ]);
+    }
+
+    #[test]
+    fn expands_macros_with_local_constants() {
+        let source = "
+            :macro blink register {
+            :const TIME 3
+                LD register, TIME
+                LD DT, register
+            }
+            :const TIME 5
+            blink V1
+            blink V2
+            LD V3, TIME
+        ";
+        assert_eq!(assemble(source).unwrap(), [0x61, 0x03, 0xF1, 0x15, 0x62, 0x03, 0xF2, 0x15, 0x63, 0x05]);
+    }
+
+    #[test]
+    fn macro_labels_are_not_visible_outside() {
+        let source = "
+            :macro spin {
+            : here
+                JP here
+            }
+            spin
+            JP here
+        ";
+        let error = assemble(source).unwrap_err();
+        assert_eq!((error.line, error.column), (7, 16));
+        assert_eq!(error.message, "'here' is not defined");
+    }
+
+    #[test]
+    fn reports_errors_with_their_location() {
+        let error = assemble("start:\n  CLS\nstart:").unwrap_err();
+        assert_eq!((error.line, error.column, error.message.as_str()), (3, 1, "'start' is already defined"));
+        let error = assemble("  LD V0, 0x100").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 10));
+        let error = Assembler::new(InstructionSet::Chip8, 0x200).assemble("SCR").unwrap_err();
+        assert_eq!(error.message, "'SCR' requires the SuperChip instruction set");
+        let error = assemble(":macro open {\n  CLS").unwrap_err();
+        assert_eq!((error.line, error.message.as_str()), (1, "Macro 'open' is never closed with '}'"));
+    }
+}
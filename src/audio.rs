@@ -0,0 +1,182 @@
+use std::f32::consts::TAU;
+#[cfg(feature = "capture")]
+use std::fs::File;
+use std::io;
+#[cfg(feature = "capture")]
+use std::io::BufWriter;
+#[cfg(feature = "capture")]
+use std::path::Path;
+
+
+/// The sample rate used when capturing audio
+pub const SAMPLE_RATE: u32 = 44100;
+
+// How long the tone takes to fade in or out, which avoids clicks when it starts and stops
+const ENVELOPE_SECONDS: f32 = 0.005;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+}
+
+/// What the emulator is playing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sound {
+    Silent,
+    /// The configured tone, played by programs that never load an audio pattern
+    Tone,
+    /// The 128 one-bit samples of an XO-CHIP audio pattern, played in a loop at a rate set by the pitch
+    Pattern { pattern: [u8; 16], pitch: u8 },
+}
+
+impl Sound {
+    /// The rate at which the samples of an audio pattern are played, 4000Hz at the default pitch of 64
+    pub fn pattern_rate(pitch: u8) -> f32 {
+        4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+    }
+}
+
+/// The tone played while the sound timer is running
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioConfig {
+    pub waveform: Waveform,
+    /// Frequency in Hz
+    pub frequency: f32,
+    /// Volume from 0 to 1
+    pub volume: f32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            waveform: Waveform::Square,
+            frequency: 440.0,
+            volume: 0.25,
+        }
+    }
+}
+
+/// Produces mono samples of the configured tone or of an audio pattern, fading in and out as sound starts and stops
+pub struct ToneGenerator {
+    config: AudioConfig,
+    sample_rate: u32,
+    // Position in the current period of the wave, from 0 to 1
+    phase: f32,
+    // Position in the audio pattern, from 0 to 128
+    pattern_position: f32,
+    // The last sound, kept to fade out with once it stops
+    last_sound: Sound,
+    // Current level of the envelope, from 0 to 1
+    level: f32,
+}
+
+impl ToneGenerator {
+    pub fn new(config: AudioConfig, sample_rate: u32) -> ToneGenerator {
+        ToneGenerator {
+            config,
+            sample_rate,
+            phase: 0.0,
+            pattern_position: 0.0,
+            last_sound: Sound::Silent,
+            level: 0.0,
+        }
+    }
+
+    /// Fills the buffer with the next samples of the given sound, fading out the previous one if it is silent
+    pub fn fill(&mut self, buffer: &mut [f32], sound: Sound) {
+        let level_step = 1.0 / (ENVELOPE_SECONDS * self.sample_rate as f32);
+        let target = if sound == Sound::Silent { 0.0 } else { 1.0 };
+        if sound != Sound::Silent {
+            self.last_sound = sound;
+        }
+        for sample in buffer {
+            self.level = if self.level < target {
+                (self.level + level_step).min(target)
+            } else {
+                (self.level - level_step).max(target)
+            };
+            *sample = self.next_wave() * self.level * self.config.volume;
+        }
+    }
+
+    fn next_wave(&mut self) -> f32 {
+        match self.last_sound {
+            Sound::Silent => 0.0,
+            Sound::Tone => {
+                let wave = match self.config.waveform {
+                    Waveform::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+                    Waveform::Sine => (self.phase * TAU).sin(),
+                };
+                self.phase = (self.phase + self.config.frequency / self.sample_rate as f32).fract();
+                wave
+            }
+            Sound::Pattern { pattern, pitch } => {
+                let bit = self.pattern_position as usize;
+                let set = pattern[bit / 8] >> (7 - bit % 8) & 1 == 1;
+                self.pattern_position = (self.pattern_position + Sound::pattern_rate(pitch) / self.sample_rate as f32) % 128.0;
+                if set { 1.0 } else { -1.0 }
+            }
+        }
+    }
+}
+
+/// Receives the audio produced while running without a window
+pub trait AudioSink {
+    /// Writes the next mono samples, at the sample rate the sink was created with
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()>;
+
+    /// Completes the output once no more samples will be written
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Captures audio to a 16-bit mono WAV file
+#[cfg(feature = "capture")]
+pub struct WavSink {
+    writer: Option<hound::WavWriter<BufWriter<File>>>,
+}
+
+#[cfg(feature = "capture")]
+impl WavSink {
+    pub fn create(path: &Path, sample_rate: u32) -> io::Result<WavSink> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let writer = hound::WavWriter::create(path, spec).map_err(to_io_error)?;
+        Ok(WavSink { writer: Some(writer) })
+    }
+}
+
+#[cfg(feature = "capture")]
+impl AudioSink for WavSink {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return Err(io::Error::other("The WAV file is already finished")),
+        };
+        for sample in samples {
+            writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).map_err(to_io_error)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.writer.take() {
+            Some(writer) => writer.finalize().map_err(to_io_error),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "capture")]
+fn to_io_error(error: hound::Error) -> io::Error {
+    match error {
+        hound::Error::IoError(error) => error,
+        error => io::Error::other(error),
+    }
+}
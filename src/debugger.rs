@@ -0,0 +1,421 @@
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use crate::disassembler::DecodedInstruction;
+use crate::emulator::{Emulator, MemoryAccess};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Register(u8),
+    Index,
+}
+
+/// Breaks when the target becomes equal to the value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub target: Target,
+    pub value: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    Paused,
+    Step,
+    Breakpoint(u16),
+    Watchpoint(MemoryAccess),
+    Condition(Condition),
+    Error(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunState {
+    Paused,
+    Running,
+    Step(usize),
+    StepOver { return_address: u16, depth: usize },
+    StepOut { depth: usize },
+}
+
+/// Controls the execution of an [`Emulator`] with breakpoints, watchpoints and stepping.
+///
+/// The debugger is driven by calling [`Debugger::step`] in place of [`Emulator::step`], and starts paused.
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<(u16, WatchKind)>,
+    conditions: Vec<Condition>,
+    state: RunState,
+    stop_reason: Option<StopReason>,
+    // Set when execution stops, so that resuming can move past a breakpoint at the current address
+    skip_breakpoint: bool,
+}
+
+const HELP: &str = "\
+Commands:
+  c, continue              resume execution
+  p, pause                 pause execution
+  s, step [count]          execute one or more instructions
+  n, next                  step over subroutine calls
+  f, finish                run until the current subroutine returns
+  b, break <addr>          add a breakpoint
+  w, watch <addr> [r|w|rw] add a memory watchpoint, on writes by default
+  when <vx|i> <value>      break when a register becomes equal to the value
+  d, delete <addr|vx|i>    remove breakpoints, watchpoints or conditions
+  l, list                  list breakpoints, watchpoints and conditions
+  r, regs                  show registers and timers
+  stack                    show the call stack
+  x, mem <addr> [len]      dump memory
+  set <vx|i|pc|dt|st> <value>  change a register
+  poke <addr> <byte>...    change memory
+  h, help                  show this message";
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            conditions: Vec::new(),
+            state: RunState::Paused,
+            stop_reason: None,
+            skip_breakpoint: false,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state == RunState::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.stop(StopReason::Paused);
+    }
+
+    pub fn resume(&mut self) {
+        self.set_state(RunState::Running);
+    }
+
+    /// Why execution last stopped, if it stopped since this was last called
+    pub fn take_stop_reason(&mut self) -> Option<StopReason> {
+        self.stop_reason.take()
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn add_watchpoint(&mut self, address: u16, kind: WatchKind) {
+        self.remove_watchpoint(address);
+        self.watchpoints.push((address, kind));
+    }
+
+    pub fn remove_watchpoint(&mut self, address: u16) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|(watched, _)| *watched != address);
+        self.watchpoints.len() != count
+    }
+
+    pub fn add_condition(&mut self, condition: Condition) {
+        self.conditions.push(condition);
+    }
+
+    pub fn remove_conditions(&mut self, target: Target) -> bool {
+        let count = self.conditions.len();
+        self.conditions.retain(|condition| condition.target != target);
+        self.conditions.len() != count
+    }
+
+    /// Executes a single instruction unless paused, stopping on breakpoints, watchpoints, conditions
+    /// and completed steps. Returns whether the display should be redrawn
+    pub fn step(&mut self, emulator: &mut Emulator) -> bool {
+        if self.is_paused() {
+            return false;
+        }
+
+        let pc = emulator.pc();
+        if self.breakpoints.contains(&pc) && !self.skip_breakpoint {
+            self.stop(StopReason::Breakpoint(pc));
+            return false;
+        }
+        self.skip_breakpoint = false;
+
+        // Watchpoints rely on the emulator recording memory accesses
+        if !self.watchpoints.is_empty() && !emulator.records_memory_accesses() {
+            emulator.set_record_memory_accesses(true);
+        }
+        let conditions_before: Vec<bool> = self.conditions.iter().map(|condition| Self::holds(condition, emulator)).collect();
+
+        let redraw = match emulator.step() {
+            Ok(redraw) => redraw,
+            Err(e) => {
+                self.stop(StopReason::Error(e.to_string()));
+                return false;
+            }
+        };
+
+        let watched = emulator.memory_accesses().iter().find(|access| self.watches(access)).copied();
+        if let Some(access) = watched {
+            self.stop(StopReason::Watchpoint(access));
+            return redraw;
+        }
+        let triggered = self.conditions.iter().zip(conditions_before)
+            .find(|(condition, held)| !held && Self::holds(condition, emulator))
+            .map(|(condition, _)| *condition);
+        if let Some(condition) = triggered {
+            self.stop(StopReason::Condition(condition));
+            return redraw;
+        }
+
+        match self.state {
+            RunState::Step(count) if count <= 1 => self.stop(StopReason::Step),
+            RunState::Step(count) => self.state = RunState::Step(count - 1),
+            RunState::StepOver { return_address, depth } if emulator.pc() == return_address && emulator.stack().len() == depth => {
+                self.stop(StopReason::Step);
+            }
+            RunState::StepOut { depth } if emulator.stack().len() < depth => self.stop(StopReason::Step),
+            _ => {}
+        }
+        redraw
+    }
+
+    /// Runs a debugger command, returning the text to show to the user
+    pub fn execute(&mut self, emulator: &mut Emulator, command: &str) -> Result<String, String> {
+        let mut words = command.split_whitespace();
+        let name = match words.next() {
+            Some(name) => name.to_lowercase(),
+            None => return Ok(String::new()),
+        };
+        let arguments: Vec<&str> = words.collect();
+
+        match name.as_str() {
+            "c" | "continue" => {
+                self.resume();
+                Ok(String::new())
+            }
+            "p" | "pause" => {
+                self.pause();
+                Ok(String::new())
+            }
+            "s" | "step" => {
+                let count = match arguments.first() {
+                    Some(count) => parse_number(count)? as usize,
+                    None => 1,
+                };
+                self.set_state(RunState::Step(count.max(1)));
+                Ok(String::new())
+            }
+            "n" | "next" => {
+                // Only subroutine calls are stepped over, anything else is a single step
+                let pc = emulator.pc();
+                if emulator.opcode_at(pc) & 0xF000 == 0x2000 {
+                    self.set_state(RunState::StepOver { return_address: pc.wrapping_add(2), depth: emulator.stack().len() });
+                } else {
+                    self.set_state(RunState::Step(1));
+                }
+                Ok(String::new())
+            }
+            "f" | "finish" => {
+                let depth = emulator.stack().len();
+                if depth == 0 {
+                    return Err("Not inside a subroutine".to_string());
+                }
+                self.set_state(RunState::StepOut { depth });
+                Ok(String::new())
+            }
+            "b" | "break" => {
+                let address = parse_number(argument(&arguments, 0)?)?;
+                self.add_breakpoint(address);
+                Ok(format!("Breakpoint at {:#05x}", address))
+            }
+            "w" | "watch" => {
+                let address = parse_number(argument(&arguments, 0)?)?;
+                let kind = match arguments.get(1).copied() {
+                    Some("r") => WatchKind::Read,
+                    Some("w") | None => WatchKind::Write,
+                    Some("rw") => WatchKind::ReadWrite,
+                    Some(kind) => return Err(format!("Unknown watchpoint kind '{}'", kind)),
+                };
+                self.add_watchpoint(address, kind);
+                Ok(format!("Watchpoint at {:#05x} ({:?})", address, kind))
+            }
+            "when" => {
+                let target = parse_target(argument(&arguments, 0)?)?;
+                let value = parse_number(argument(&arguments, 1)?)?;
+                self.add_condition(Condition { target, value });
+                Ok(format!("Breaking when {} == {:#x}", target, value))
+            }
+            "d" | "delete" => {
+                let what = argument(&arguments, 0)?;
+                let removed = match parse_target(what) {
+                    Ok(target) => self.remove_conditions(target),
+                    Err(_) => {
+                        let address = parse_number(what)?;
+                        self.remove_breakpoint(address) | self.remove_watchpoint(address)
+                    }
+                };
+                if removed { Ok(String::new()) } else { Err(format!("Nothing to delete at '{}'", what)) }
+            }
+            "l" | "list" => {
+                let mut lines: Vec<String> = self.breakpoints.iter().map(|address| format!("Breakpoint at {:#05x}", address)).collect();
+                lines.extend(self.watchpoints.iter().map(|(address, kind)| format!("Watchpoint at {:#05x} ({:?})", address, kind)));
+                lines.extend(self.conditions.iter().map(|condition| format!("Break when {} == {:#x}", condition.target, condition.value)));
+                Ok(lines.join("\n"))
+            }
+            "r" | "regs" => Ok(format_registers(emulator)),
+            "stack" => {
+                Ok(emulator.stack().iter().enumerate().rev()
+                    .map(|(depth, address)| format!("#{} {:#05x}", depth, address))
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+            "x" | "mem" => {
+                let address = parse_number(argument(&arguments, 0)?)?;
+                let length = match arguments.get(1) {
+                    Some(length) => parse_number(length)?,
+                    None => 64,
+                };
+                Ok(format_memory(emulator, address, length))
+            }
+            "set" => {
+                let value = parse_number(argument(&arguments, 1)?)?;
+                match argument(&arguments, 0)?.to_lowercase().as_str() {
+                    "pc" => emulator.set_pc(value),
+                    "dt" => emulator.delay_timer = value as u8,
+                    "st" => emulator.sound_timer = value as u8,
+                    target => match parse_target(target)? {
+                        Target::Register(register) => emulator.set_register(register as usize, value as u8),
+                        Target::Index => emulator.set_index(value),
+                    },
+                }
+                Ok(String::new())
+            }
+            "poke" => {
+                let address = parse_number(argument(&arguments, 0)?)?;
+                if arguments.len() < 2 {
+                    return Err("Expected at least one byte".to_string());
+                }
+                for (offset, byte) in arguments[1..].iter().enumerate() {
+                    emulator.poke(address.wrapping_add(offset as u16), parse_number(byte)? as u8);
+                }
+                Ok(String::new())
+            }
+            "h" | "help" => Ok(HELP.to_string()),
+            _ => Err(format!("Unknown command '{}', type 'help' for a list of commands", name)),
+        }
+    }
+
+    fn set_state(&mut self, state: RunState) {
+        self.state = state;
+    }
+
+    fn stop(&mut self, reason: StopReason) {
+        self.state = RunState::Paused;
+        self.stop_reason = Some(reason);
+        // Resuming on a breakpoint must not stop on it again straight away
+        self.skip_breakpoint = true;
+    }
+
+    fn watches(&self, access: &MemoryAccess) -> bool {
+        self.watchpoints.iter().any(|(address, kind)| match (access, kind) {
+            (MemoryAccess::Read(accessed), WatchKind::Read | WatchKind::ReadWrite) => accessed == address,
+            (MemoryAccess::Write(accessed), WatchKind::Write | WatchKind::ReadWrite) => accessed == address,
+            _ => false,
+        })
+    }
+
+    fn holds(condition: &Condition, emulator: &Emulator) -> bool {
+        match condition.target {
+            Target::Register(register) => emulator.registers()[register as usize] as u16 == condition.value,
+            Target::Index => emulator.index() == condition.value,
+        }
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Target::Register(register) => write!(f, "V{:X}", register),
+            Target::Index => write!(f, "I"),
+        }
+    }
+}
+
+impl Display for StopReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopReason::Paused => write!(f, "Paused"),
+            StopReason::Step => write!(f, "Stepped"),
+            StopReason::Breakpoint(address) => write!(f, "Hit breakpoint at {:#05x}", address),
+            StopReason::Watchpoint(MemoryAccess::Read(address)) => write!(f, "Watched address {:#05x} was read", address),
+            StopReason::Watchpoint(MemoryAccess::Write(address)) => write!(f, "Watched address {:#05x} was written", address),
+            StopReason::Condition(condition) => write!(f, "{} became {:#x}", condition.target, condition.value),
+            StopReason::Error(error) => write!(f, "Emulator error: {}", error),
+        }
+    }
+}
+
+pub fn format_registers(emulator: &Emulator) -> String {
+    let registers: Vec<String> = emulator.registers().iter().enumerate()
+        .map(|(i, value)| format!("V{:X}: {:#04x}", i, value))
+        .collect();
+    let pc = emulator.pc();
+    let bytes: Vec<u8> = (0..4).map(|offset| emulator.peek(pc.wrapping_add(offset))).collect();
+    let instruction = DecodedInstruction::decode(&bytes, pc, emulator.instruction_set())
+        .map(|instruction| instruction.format(|_| None))
+        .unwrap_or_else(|| "invalid".to_string());
+    format!(
+        "PC: {:#05x} ({:#06x} {})  I: {:#05x}  SP: {}  DT: {}  ST: {}\n{}\n{}",
+        pc, emulator.opcode_at(pc), instruction, emulator.index(), emulator.stack().len(),
+        emulator.delay_timer, emulator.sound_timer, registers[..8].join("  "), registers[8..].join("  ")
+    )
+}
+
+pub fn format_memory(emulator: &Emulator, address: u16, length: u16) -> String {
+    let mut lines = Vec::new();
+    for line_start in (0..length).step_by(16) {
+        let line_address = address.wrapping_add(line_start);
+        let bytes: Vec<String> = (0..16.min(length - line_start))
+            .map(|offset| format!("{:02x}", emulator.peek(line_address.wrapping_add(offset))))
+            .collect();
+        lines.push(format!("{:#06x}: {}", line_address, bytes.join(" ")));
+    }
+    lines.join("\n")
+}
+
+fn argument<'a>(arguments: &[&'a str], index: usize) -> Result<&'a str, String> {
+    arguments.get(index).copied().ok_or_else(|| format!("Missing argument {}", index + 1))
+}
+
+// Numbers are decimal, or hexadecimal when prefixed with 0x
+fn parse_number(text: &str) -> Result<u16, String> {
+    let result = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    result.map_err(|_| format!("Invalid number '{}'", text))
+}
+
+fn parse_target(text: &str) -> Result<Target, String> {
+    let lower = text.to_lowercase();
+    if lower == "i" {
+        return Ok(Target::Index);
+    }
+    match lower.strip_prefix('v').map(|register| u8::from_str_radix(register, 16)) {
+        Some(Ok(register)) if register < 16 => Ok(Target::Register(register)),
+        _ => Err(format!("Invalid register '{}'", text)),
+    }
+}
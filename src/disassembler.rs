@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use crate::emulator::InstructionSet;
+use crate::opcodes::{self, OpcodeInfo, Operand};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Jump,
+    Subroutine,
+}
+
+/// A decoded instruction at some address of a rom
+#[derive(Debug, Clone, Copy)]
+pub struct DecodedInstruction {
+    pub address: u16,
+    pub opcode: u16,
+    pub next_word: u16,
+    pub info: &'static OpcodeInfo,
+}
+
+/// Turns roms into Cowgod style assembly, following control flow from the load address to tell code from data.
+///
+/// The output can be read back by the assembler: labels are declared with `: name`, data is emitted with
+/// `:byte` and comments start with `#`.
+pub struct Disassembler<'a> {
+    rom: &'a [u8],
+    load_address: u16,
+    instruction_set: InstructionSet,
+    // Maps the address of each reachable instruction to the instruction
+    code: BTreeMap<u16, DecodedInstruction>,
+    labels: BTreeMap<u16, LabelKind>,
+}
+
+impl DecodedInstruction {
+    /// Decodes the instruction at the start of `bytes`, which must hold at least two bytes
+    pub fn decode(bytes: &[u8], address: u16, instruction_set: InstructionSet) -> Option<DecodedInstruction> {
+        let word = |offset: usize| bytes.get(offset..offset + 2).map(|word| (word[0] as u16) << 8 | word[1] as u16);
+        let opcode = word(0)?;
+        let info = opcodes::decode(opcode, instruction_set)?;
+        let next_word = if info.size() == 4 { word(2)? } else { 0 };
+        Some(DecodedInstruction { address, opcode, next_word, info })
+    }
+
+    pub fn size(&self) -> u16 {
+        self.info.size()
+    }
+
+    /// The address this instruction refers to, if any
+    pub fn target(&self) -> Option<u16> {
+        self.info.operands.iter()
+            .find(|operand| matches!(operand, Operand::Address | Operand::LongAddress))
+            .map(|operand| OpcodeInfo::operand_value(*operand, self.opcode, self.next_word))
+    }
+
+    /// Formats the instruction, naming referenced addresses with the given function when it returns a name
+    pub fn format(&self, name_address: impl Fn(u16) -> Option<String>) -> String {
+        let operands: Vec<String> = self.info.operands.iter().map(|operand| {
+            let value = OpcodeInfo::operand_value(*operand, self.opcode, self.next_word);
+            match operand {
+                Operand::Vx | Operand::Vy => format!("V{:X}", value),
+                Operand::Byte => format!("{:#04x}", value),
+                Operand::Nibble | Operand::XNibble => format!("{}", value),
+                Operand::Address | Operand::LongAddress => name_address(value).unwrap_or_else(|| format!("{:#05x}", value)),
+                Operand::Keyword(keyword) => keyword.to_string(),
+            }
+        }).collect();
+        if operands.is_empty() {
+            self.info.mnemonic.to_string()
+        } else {
+            format!("{} {}", self.info.mnemonic, operands.join(", "))
+        }
+    }
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(rom: &'a [u8], load_address: u16, instruction_set: InstructionSet) -> Disassembler<'a> {
+        let mut disassembler = Disassembler {
+            rom,
+            load_address,
+            instruction_set,
+            code: BTreeMap::new(),
+            labels: BTreeMap::new(),
+        };
+        disassembler.trace(load_address);
+        disassembler
+    }
+
+    /// Decodes the instruction at an address of the rom
+    pub fn decode(&self, address: u16) -> Option<DecodedInstruction> {
+        let offset = address.checked_sub(self.load_address)? as usize;
+        DecodedInstruction::decode(self.rom.get(offset..)?, address, self.instruction_set)
+    }
+
+    // Follows every path of execution from the entry point, recording instructions and labels
+    fn trace(&mut self, entry: u16) {
+        let mut pending = vec![entry];
+        while let Some(address) = pending.pop() {
+            if self.code.contains_key(&address) {
+                continue;
+            }
+            let instruction = match self.decode(address) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+            self.code.insert(address, instruction);
+            let next = address.wrapping_add(instruction.size());
+
+            match instruction.opcode & 0xF000 {
+                0x0000 if matches!(instruction.opcode, 0x00EE | 0x00FD) => {}
+                0x1000 => {
+                    let target = instruction.opcode & 0x0FFF;
+                    self.add_label(target, LabelKind::Jump);
+                    pending.push(target);
+                }
+                0x2000 => {
+                    let target = instruction.opcode & 0x0FFF;
+                    self.add_label(target, LabelKind::Subroutine);
+                    pending.push(target);
+                    pending.push(next);
+                }
+                // Jumps with offset usually index a table of jumps, so the table is followed as code
+                0xB000 => {
+                    let target = instruction.opcode & 0x0FFF;
+                    self.add_label(target, LabelKind::Jump);
+                    pending.push(target);
+                }
+                0x3000 | 0x4000 | 0x9000 | 0xE000 => self.push_skip(&mut pending, next),
+                0x5000 if instruction.opcode & 0x000F == 0 => self.push_skip(&mut pending, next),
+                _ => {
+                    if let Some(target) = instruction.target() {
+                        self.add_label(target, LabelKind::Data);
+                    }
+                    pending.push(next);
+                }
+            }
+        }
+    }
+
+    // Conditional skips continue at the next instruction or the one after it
+    fn push_skip(&self, pending: &mut Vec<u16>, next: u16) {
+        pending.push(next);
+        let skipped_size = self.decode(next).map(|instruction| instruction.size()).unwrap_or(2);
+        pending.push(next.wrapping_add(skipped_size));
+    }
+
+    fn add_label(&mut self, address: u16, kind: LabelKind) {
+        let label = self.labels.entry(address).or_insert(kind);
+        *label = (*label).max(kind);
+    }
+
+    /// The name of the label at an address, if the address is referenced and the label can be placed there
+    pub fn label(&self, address: u16) -> Option<String> {
+        let in_rom = address >= self.load_address && ((address - self.load_address) as usize) < self.rom.len();
+        // Addresses in the middle of an instruction cannot be labelled
+        let inside_instruction = !self.code.contains_key(&address) && self.code.range(..address).next_back()
+            .is_some_and(|(start, instruction)| start.wrapping_add(instruction.size()) > address);
+        match self.labels.get(&address) {
+            Some(kind) if in_rom && !inside_instruction => Some(match kind {
+                LabelKind::Subroutine => format!("sub_{:03x}", address),
+                LabelKind::Jump => format!("label_{:03x}", address),
+                LabelKind::Data => format!("data_{:03x}", address),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Instructions reachable from the entry point, by address
+    pub fn instructions(&self) -> impl Iterator<Item = &DecodedInstruction> {
+        self.code.values()
+    }
+
+    /// Produces the assembly listing of the whole rom
+    pub fn disassemble(&self) -> String {
+        let mut output = String::new();
+        let end = self.load_address as usize + self.rom.len();
+        let mut address = self.load_address as usize;
+        let mut data: Vec<u8> = Vec::new();
+        let mut data_start = address;
+
+        while address < end {
+            let label = self.label(address as u16);
+            let instruction = self.code.get(&(address as u16));
+            // Data runs are broken at labels, instructions and every 8 bytes
+            if !data.is_empty() && (label.is_some() || instruction.is_some() || data.len() == 8) {
+                Self::write_data(&mut output, data_start, &data);
+                data.clear();
+            }
+            if let Some(label) = label {
+                output.push_str(&format!(": {}\n", label));
+            }
+            match instruction {
+                Some(instruction) => {
+                    let text = instruction.format(|target| self.label(target));
+                    let bytes = if instruction.size() == 4 {
+                        format!("{:04x} {:04x}", instruction.opcode, instruction.next_word)
+                    } else {
+                        format!("{:04x}", instruction.opcode)
+                    };
+                    output.push_str(&format!("\t{:<24} # {:#05x}: {}\n", text, address, bytes));
+                    address += instruction.size() as usize;
+                }
+                None => {
+                    if data.is_empty() {
+                        data_start = address;
+                    }
+                    data.push(self.rom[address - self.load_address as usize]);
+                    address += 1;
+                }
+            }
+        }
+        if !data.is_empty() {
+            Self::write_data(&mut output, data_start, &data);
+        }
+        output
+    }
+
+    fn write_data(output: &mut String, address: usize, data: &[u8]) {
+        let bytes: Vec<String> = data.iter().map(|byte| format!("{:#04x}", byte)).collect();
+        output.push_str(&format!("\t{:<24} # {:#05x}\n", format!(":byte {}", bytes.join(" ")), address));
+    }
+}
@@ -0,0 +1,170 @@
+#[cfg(feature = "capture")]
+use std::io::Write;
+use std::str::FromStr;
+use crate::emulator::{DISPLAY_HEIGHT, DISPLAY_WIDTH, Emulator};
+use crate::save_state::crc32;
+
+
+/// Colours for each combination of the two bitplanes, as RGB. Only XO-CHIP programs use the last two
+pub const PALETTE: [[u8; 3]; 4] = [
+    [0, 0, 0],
+    [255, 255, 255],
+    [170, 170, 170],
+    [85, 85, 85],
+];
+
+/// Named palettes: the default greys, the colours of the Octo XO-CHIP environment, and the green and amber of
+/// phosphor monitors, where the second plane is drawn in dimmer shades
+pub const PALETTE_PRESETS: [(&str, [[u8; 3]; 4]); 4] = [
+    ("default", PALETTE),
+    ("xo-chip", [[0x99, 0x66, 0x00], [0xff, 0xcc, 0x00], [0xff, 0x66, 0x00], [0x66, 0x22, 0x00]]),
+    ("green", [[0x0a, 0x14, 0x0a], [0x33, 0xff, 0x66], [0x1a, 0x99, 0x3d], [0x22, 0xcc, 0x50]]),
+    ("amber", [[0x14, 0x0c, 0x00], [0xff, 0xb0, 0x00], [0x99, 0x69, 0x00], [0xcc, 0x8c, 0x00]]),
+];
+
+/// The colours pixels are drawn with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    /// The colour of each combination of the two bitplanes, as RGB
+    pub colors: [[u8; 3]; 4],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette { colors: PALETTE }
+    }
+}
+
+impl Palette {
+    /// The palette of one of the [`PALETTE_PRESETS`]
+    pub fn preset(name: &str) -> Option<Palette> {
+        PALETTE_PRESETS.iter()
+            .find(|(preset, _)| preset.eq_ignore_ascii_case(name))
+            .map(|(_, colors)| Palette { colors: *colors })
+    }
+
+    pub fn color(&self, pixel: u8) -> [u8; 3] {
+        self.colors[pixel as usize & 3]
+    }
+}
+
+/// Parses the name of one of the [`PALETTE_PRESETS`], or two to four colours written in hex and separated by commas,
+/// such as `000000,ffffff`. They are the colours of unset pixels, of pixels set in the first plane, in the second plane
+/// and in both, and those left out keep their default
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if let Some(palette) = Palette::preset(text.trim()) {
+            return Ok(palette);
+        }
+        let colors: Vec<&str> = text.split(',').map(str::trim).collect();
+        if !(2..=4).contains(&colors.len()) {
+            let presets: Vec<&str> = PALETTE_PRESETS.iter().map(|(name, _)| *name).collect();
+            return Err(format!("Expected one of {} or two to four colours separated by commas, such as `000000,ffffff`", presets.join(", ")));
+        }
+        let mut palette = Palette::default();
+        for (index, color) in colors.into_iter().enumerate() {
+            let hex = color.strip_prefix('#').unwrap_or(color);
+            let value = match u32::from_str_radix(hex, 16) {
+                Ok(value) if hex.len() == 6 => value,
+                _ => return Err(format!("`{}` is not a colour, expected six hex digits such as `ff8000`", color)),
+            };
+            palette.colors[index] = [(value >> 16) as u8, (value >> 8) as u8, value as u8];
+        }
+        Ok(palette)
+    }
+}
+
+/// A picture of the visible part of the display, as presented by frontends
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    /// The colour of every pixel as RGB, in rows from the top
+    pub pixels: Vec<[u8; 3]>,
+}
+
+/// How the display is scaled to fit a window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scaling {
+    /// As large as fits
+    Fit,
+    /// The largest whole multiple of the high resolution size that fits, so every pixel is as large as every other
+    Integer,
+}
+
+impl Scaling {
+    /// Where the display goes in a window of the given size, as its position and size. The aspect ratio is kept and the
+    /// display centered, whatever the resolution
+    pub fn place(&self, window_width: u32, window_height: u32) -> (u32, u32, u32, u32) {
+        let (display_width, display_height) = (DISPLAY_WIDTH as u32, DISPLAY_HEIGHT as u32);
+        let (width, height) = match self {
+            Scaling::Fit if window_width * display_height > window_height * display_width => {
+                (window_height * display_width / display_height, window_height)
+            }
+            Scaling::Fit => (window_width, window_width * display_height / display_width),
+            Scaling::Integer => {
+                let scale = (window_width / display_width).min(window_height / display_height).max(1);
+                (display_width * scale, display_height * scale)
+            }
+        };
+        (window_width.saturating_sub(width) / 2, window_height.saturating_sub(height) / 2, width, height)
+    }
+}
+
+// Characters for each combination of the two bitplanes in text dumps
+const TEXT_PIXELS: [char; 4] = ['.', '#', '+', '%'];
+
+/// Renders the visible part of the display as text, one line per row, with `.` for unset pixels and `#` for set ones.
+/// XO-CHIP pixels set only in the second plane are drawn as `+` and pixels set in both as `%`
+pub fn render_text(emulator: &Emulator) -> String {
+    let mut text = String::new();
+    for row in emulator.display.iter().take(emulator.display_height()) {
+        text.extend(row.iter().take(emulator.display_width()).map(|pixel| TEXT_PIXELS[*pixel as usize & 3]));
+        text.push('\n');
+    }
+    text
+}
+
+/// A CRC-32 of the whole display, to tell whether two runs ended up showing the same thing
+pub fn display_hash(emulator: &Emulator) -> u32 {
+    crc32(&emulator.display.concat())
+}
+
+/// The size of captures at the given scale, which is the size of a low resolution pixel. High resolution pixels are half
+/// as big, so the size doesn't change with the resolution
+pub fn capture_size(scale: usize) -> (usize, usize) {
+    (DISPLAY_WIDTH / 2 * scale, DISPLAY_HEIGHT / 2 * scale)
+}
+
+/// The visible part of the display as RGB pixels, in rows from the top, at the size given by [`capture_size`]
+pub fn render_rgb(emulator: &Emulator, palette: &Palette, scale: usize) -> Vec<u8> {
+    render_indexed(emulator, scale).into_iter().flat_map(|pixel| palette.color(pixel)).collect()
+}
+
+/// The visible part of the display as indices into a palette, in rows from the top, at the size given by
+/// [`capture_size`]. With an odd scale, high resolution pixels alternate between two sizes
+pub fn render_indexed(emulator: &Emulator, scale: usize) -> Vec<u8> {
+    let (width, height) = capture_size(scale);
+    let (display_width, display_height) = (emulator.display_width(), emulator.display_height());
+    let mut data = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = &emulator.display[y * display_height / height];
+        data.extend((0..width).map(|x| row[x * display_width / width] & 3));
+    }
+    data
+}
+
+/// Writes the visible part of the display as an RGB PNG at the size given by [`capture_size`]
+#[cfg(feature = "capture")]
+pub fn write_png<W: Write>(emulator: &Emulator, writer: W, palette: &Palette, scale: usize) -> Result<(), png::EncodingError> {
+    let (width, height) = capture_size(scale);
+    let data = render_rgb(emulator, palette, scale);
+
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)
+}
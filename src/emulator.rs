@@ -1,9 +1,14 @@
 use std::fmt::{Display, Formatter};
-use rand::prelude::*;
 use thiserror::Error;
+use crate::audio::Sound;
+use crate::opcodes;
+use crate::random::Random;
+use crate::quirks::{Platform, Quirks};
+use crate::save_state::{self, SaveStateError, StateReader, StateWriter};
+use crate::timing::{self, Timing, CYCLES_PER_FRAME, INTERRUPT_CYCLES};
 
 
-const FONT: [u8; 80] = [
+pub(crate) const FONT: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
@@ -22,14 +27,41 @@ const FONT: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80  // F
 ];
 
+const BIG_FONT: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+];
+
 const CODE_BASE_ADDRESS: u16 = 0x200;
 const FONT_BASE_ADDRESS: u16 = 0x50;
+const STACK_DEPTH: usize = 128;
+
+const MEMORY_SIZE: usize = 0x1000;
+const XO_CHIP_MEMORY_SIZE: usize = 0x10000;
+const MAX_MEMORY_SIZE: usize = 0x10000;
+
+pub const DISPLAY_WIDTH: usize = 128;
+pub const DISPLAY_HEIGHT: usize = 64;
 
 
 #[derive(Error, Debug)]
 pub enum EmulatorError {
-    #[error("Program size is {0} bytes but cannot exceed 3584 bytes")]
-    ProgramTooLarge(usize),
+    #[error("Program size is {0} bytes but cannot exceed {1} bytes")]
+    ProgramTooLarge(usize, usize),
     #[error("The program counter reached the end of memory")]
     PcOutOfBounds(),
     #[error("A decoded instruction is invalid: {0}")]
@@ -38,6 +70,22 @@ pub enum EmulatorError {
     PoppedEmptyStack(),
     #[error("Tried to push a value to a full stack")]
     StackOverflow,
+    #[error("Memory size is {0} bytes but must be between 1 and 65536 bytes")]
+    InvalidMemorySize(usize),
+    #[error("Load address {0:#05x} is outside of memory")]
+    InvalidLoadAddress(u16),
+    #[error("Font at address {0:#05x} does not fit in memory or overlaps the program")]
+    InvalidFontAddress(u16),
+    #[error("Stack depth is {0} but must be between 1 and 65535")]
+    InvalidStackDepth(usize),
+}
+
+// Each instruction set includes the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InstructionSet {
+    Chip8,
+    SuperChip,
+    XoChip,
 }
 
 #[derive(Debug)]
@@ -51,19 +99,57 @@ pub struct Instruction {
 }
 
 pub struct Emulator {
-    memory: [u8; 0x1000],
-    pub display: [[bool; 64]; 32],
+    memory: Vec<u8>,
+    /// Each pixel holds one bit per bitplane. Only XO-CHIP programs can draw to the second plane
+    pub display: [[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+    instruction_set: InstructionSet,
+    hires: bool,
+    halted: bool,
+    rpl_flags: [u8; 16],
+    planes: u8,
+    /// The XO-CHIP audio pattern, once a program loads one. Until then the plain tone is played
+    pub audio_pattern: Option<[u8; 16]>,
+    pub pitch: u8,
     pc: u16,
     index: u16,
-    stack: [u16; 128],
+    stack: Vec<u16>,
     sp: usize,
     pub delay_timer: u8,
     pub sound_timer: u8,
     registers: [u8; 16],
     pub keypad: [bool; 16],
-    shift_sets_vx: bool,
-    jump_with_offset_bug_emulation: bool,
-    increment_i_on_store_and_load: bool,
+    quirks: Quirks,
+    vblank: bool,
+    pressed_key: Option<u8>,
+    font_address: u16,
+    random: Random,
+    timing: Timing,
+    // Machine cycles run in total and in the current frame, under the COSMAC VIP timing model
+    cycles: u64,
+    frame_cycles: u32,
+    // Instructions run since the emulator was built, which save states leave alone
+    steps: u64,
+    rom_hash: u32,
+    record_memory_accesses: bool,
+    memory_accesses: Vec<MemoryAccess>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryAccess {
+    Read(u16),
+    Write(u16),
+}
+
+pub struct EmulatorBuilder {
+    program: Vec<u8>,
+    instruction_set: InstructionSet,
+    quirks: Quirks,
+    load_address: u16,
+    font_address: u16,
+    stack_depth: usize,
+    memory_size: Option<usize>,
+    seed: Option<u64>,
+    timing: Timing,
 }
 
 
@@ -93,38 +179,446 @@ impl Display for Instruction {
     }
 }
 
-impl Emulator {
-    pub fn new(program: &[u8], shift_sets_vx: bool, jump_with_offset_bug_emulation: bool, increment_i_on_store_and_load: bool) -> Result<Emulator, EmulatorError> {
-        if program.len() > 0x1000 - CODE_BASE_ADDRESS as usize {
-            return Err(EmulatorError::ProgramTooLarge(program.len()));
+impl EmulatorBuilder {
+    pub fn new(program: &[u8]) -> EmulatorBuilder {
+        EmulatorBuilder {
+            program: program.to_vec(),
+            instruction_set: InstructionSet::Chip8,
+            quirks: Quirks::default(),
+            load_address: CODE_BASE_ADDRESS,
+            font_address: FONT_BASE_ADDRESS,
+            stack_depth: STACK_DEPTH,
+            memory_size: None,
+            seed: None,
+            timing: Timing::Fixed,
         }
+    }
+
+    /// Sets both the instruction set and the quirks to the ones of the given platform
+    pub fn platform(mut self, platform: Platform) -> EmulatorBuilder {
+        self.instruction_set = platform.instruction_set();
+        self.quirks = platform.quirks();
+        self
+    }
+
+    pub fn instruction_set(mut self, instruction_set: InstructionSet) -> EmulatorBuilder {
+        self.instruction_set = instruction_set;
+        self
+    }
 
-        let mut memory = [0; 0x1000];
-        for (i, byte) in program.iter().enumerate() {
-            memory[i + CODE_BASE_ADDRESS as usize] = *byte;
+    pub fn quirks(mut self, quirks: Quirks) -> EmulatorBuilder {
+        self.quirks = quirks;
+        self
+    }
+
+    /// Address the program is loaded at and starts executing from. Defaults to 0x200
+    pub fn load_address(mut self, load_address: u16) -> EmulatorBuilder {
+        self.load_address = load_address;
+        self
+    }
+
+    /// Address of the built-in font. The big SUPER-CHIP font is placed right after it. Defaults to 0x50
+    pub fn font_address(mut self, font_address: u16) -> EmulatorBuilder {
+        self.font_address = font_address;
+        self
+    }
+
+    /// Maximum number of nested subroutine calls, up to 65535. Defaults to 128
+    pub fn stack_depth(mut self, stack_depth: usize) -> EmulatorBuilder {
+        self.stack_depth = stack_depth;
+        self
+    }
+
+    /// Size of the addressable memory. Defaults to 4 KiB, or 64 KiB for XO-CHIP
+    pub fn memory_size(mut self, memory_size: usize) -> EmulatorBuilder {
+        self.memory_size = Some(memory_size);
+        self
+    }
+
+    /// Seed of the random number generator used by the CXNN instruction. Defaults to a seed from the system's
+    /// entropy source
+    pub fn seed(mut self, seed: u64) -> EmulatorBuilder {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// How long instructions take to run. Defaults to a fixed time per instruction
+    pub fn timing(mut self, timing: Timing) -> EmulatorBuilder {
+        self.timing = timing;
+        self
+    }
+
+    pub fn build(self) -> Result<Emulator, EmulatorError> {
+        let memory_size = self.memory_size.unwrap_or(match self.instruction_set {
+            InstructionSet::XoChip => XO_CHIP_MEMORY_SIZE,
+            _ => MEMORY_SIZE,
+        });
+        if memory_size == 0 || memory_size > MAX_MEMORY_SIZE {
+            return Err(EmulatorError::InvalidMemorySize(memory_size));
+        }
+        let load_address = self.load_address as usize;
+        if load_address >= memory_size {
+            return Err(EmulatorError::InvalidLoadAddress(self.load_address));
+        }
+        if self.program.len() > memory_size - load_address {
+            return Err(EmulatorError::ProgramTooLarge(self.program.len(), memory_size - load_address));
         }
-        for (i, byte) in FONT.iter().enumerate() {
-            memory[i + FONT_BASE_ADDRESS as usize] = *byte;
+        let font_address = self.font_address as usize;
+        let font_end = font_address + FONT.len() + BIG_FONT.len();
+        if font_end > memory_size || (font_address < load_address + self.program.len() && load_address < font_end) {
+            return Err(EmulatorError::InvalidFontAddress(self.font_address));
         }
+        // Save states store the depth in 16 bits
+        if self.stack_depth == 0 || self.stack_depth > u16::MAX as usize {
+            return Err(EmulatorError::InvalidStackDepth(self.stack_depth));
+        }
+
+        let mut memory = vec![0; memory_size];
+        memory[load_address..load_address + self.program.len()].copy_from_slice(&self.program);
+        memory[font_address..font_address + FONT.len()].copy_from_slice(&FONT);
+        memory[font_address + FONT.len()..font_end].copy_from_slice(&BIG_FONT);
 
         Ok(Emulator {
             memory,
-            display: [[false; 64]; 32],
-            pc: 0x200,
+            display: [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+            instruction_set: self.instruction_set,
+            hires: false,
+            halted: false,
+            rpl_flags: [0; 16],
+            planes: 1,
+            audio_pattern: None,
+            pitch: 64,
+            pc: self.load_address,
             index: 0,
-            stack: [0; 128],
+            stack: vec![0; self.stack_depth],
             sp: 0,
             delay_timer: 0,
             sound_timer: 0,
             registers: [0; 16],
             keypad: [false; 16],
-            shift_sets_vx,
-            jump_with_offset_bug_emulation,
-            increment_i_on_store_and_load,
+            quirks: self.quirks,
+            vblank: false,
+            pressed_key: None,
+            font_address: self.font_address,
+            random: match self.seed {
+                Some(seed) => Random::new(seed),
+                None => Random::from_entropy(),
+            },
+            timing: self.timing,
+            cycles: 0,
+            frame_cycles: INTERRUPT_CYCLES,
+            steps: 0,
+            rom_hash: save_state::crc32(&self.program),
+            record_memory_accesses: false,
+            memory_accesses: Vec::new(),
         })
     }
+}
+
+impl Emulator {
+    pub fn new(program: &[u8], instruction_set: InstructionSet, quirks: Quirks) -> Result<Emulator, EmulatorError> {
+        EmulatorBuilder::new(program)
+            .instruction_set(instruction_set)
+            .quirks(quirks)
+            .build()
+    }
+
+    pub fn builder(program: &[u8]) -> EmulatorBuilder {
+        EmulatorBuilder::new(program)
+    }
+
+    pub fn instruction_set(&self) -> InstructionSet {
+        self.instruction_set
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    /// Machine cycles run so far under the COSMAC VIP timing model, including the interrupts. Always 0 with fixed
+    /// timing
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Whether a frame in which the given number of instructions have run is over. Under the COSMAC VIP timing model
+    /// that is once the instructions have used up the cycles the interrupt leaves, otherwise once
+    /// `instructions_per_frame` have run. A halted program always completes the frame
+    pub fn frame_complete(&self, instructions: usize, instructions_per_frame: usize) -> bool {
+        self.halted || match self.timing {
+            Timing::Fixed => instructions >= instructions_per_frame,
+            Timing::CosmacVip => self.frame_cycles >= CYCLES_PER_FRAME,
+        }
+    }
+
+    /// CRC-32 of the loaded program, used to check that save states belong to it
+    pub fn rom_hash(&self) -> u32 {
+        self.rom_hash
+    }
+
+    /// Serializes the full machine state into the versioned save state format
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.u8(self.instruction_set as u8);
+        writer.u16(self.quirks.to_bits());
+        writer.u32(self.memory.len() as u32);
+        writer.bytes(&self.memory);
+        writer.bool(self.hires);
+        writer.u8(self.planes);
+        // Pack the display one bit per pixel, one bitplane after the other
+        for plane in [1u8, 2] {
+            for row in self.display.iter() {
+                for pixels in row.chunks(8) {
+                    writer.u8(pixels.iter().fold(0, |byte, pixel| byte << 1 | (pixel & plane != 0) as u8));
+                }
+            }
+        }
+        writer.u16(self.pc);
+        writer.u16(self.index);
+        writer.u16(self.stack.len() as u16);
+        for address in self.stack.iter() {
+            writer.u16(*address);
+        }
+        writer.u16(self.sp as u16);
+        writer.u8(self.delay_timer);
+        writer.u8(self.sound_timer);
+        writer.bytes(&self.registers);
+        writer.u16(self.keypad.iter().enumerate().fold(0, |bits, (i, state)| bits | (*state as u16) << i));
+        writer.bytes(&self.rpl_flags);
+        writer.bool(self.audio_pattern.is_some());
+        writer.bytes(&self.audio_pattern.unwrap_or_default());
+        writer.u8(self.pitch);
+        writer.bool(self.halted);
+        writer.bool(self.vblank);
+        writer.u8(self.pressed_key.unwrap_or(0xFF));
+        writer.u16(self.font_address);
+        self.random.write(&mut writer);
+        writer.u8(self.timing as u8);
+        writer.u64(self.cycles);
+        writer.u32(self.frame_cycles);
+        save_state::encode(self.rom_hash, &writer.finish())
+    }
+
+    /// Restores a state produced by [`Emulator::save_state`]. The emulator is left untouched if the state is invalid
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = StateReader::new(save_state::decode(data, self.rom_hash)?);
+        let instruction_set = match reader.u8()? {
+            0 => InstructionSet::Chip8,
+            1 => InstructionSet::SuperChip,
+            2 => InstructionSet::XoChip,
+            _ => return Err(SaveStateError::InvalidData("instruction set")),
+        };
+        let quirks = Quirks::from_bits(reader.u16()?);
+        let memory_size = reader.u32()? as usize;
+        if memory_size == 0 || memory_size > MAX_MEMORY_SIZE {
+            return Err(SaveStateError::InvalidData("memory size"));
+        }
+        let memory = reader.bytes(memory_size)?.to_vec();
+        let hires = reader.bool()?;
+        let planes = reader.u8()?;
+        if planes > 0x3 {
+            return Err(SaveStateError::InvalidData("bitplanes"));
+        }
+        let mut display = [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+        for plane in [1u8, 2] {
+            for row in display.iter_mut() {
+                for pixels in row.chunks_mut(8) {
+                    let byte = reader.u8()?;
+                    for (i, pixel) in pixels.iter_mut().enumerate() {
+                        if byte >> (7 - i) & 1 == 1 {
+                            *pixel |= plane;
+                        }
+                    }
+                }
+            }
+        }
+        let pc = reader.u16()?;
+        let index = reader.u16()?;
+        let stack_depth = reader.u16()? as usize;
+        if stack_depth == 0 {
+            return Err(SaveStateError::InvalidData("stack depth"));
+        }
+        let mut stack = Vec::with_capacity(stack_depth);
+        for _ in 0..stack_depth {
+            stack.push(reader.u16()?);
+        }
+        let sp = reader.u16()? as usize;
+        if sp > stack_depth {
+            return Err(SaveStateError::InvalidData("stack pointer"));
+        }
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let registers = reader.bytes(16)?.try_into().unwrap();
+        let keypad_bits = reader.u16()?;
+        let rpl_flags = reader.bytes(16)?.try_into().unwrap();
+        let has_audio_pattern = reader.bool()?;
+        let audio_pattern: [u8; 16] = reader.bytes(16)?.try_into().unwrap();
+        let pitch = reader.u8()?;
+        let halted = reader.bool()?;
+        let vblank = reader.bool()?;
+        let pressed_key = match reader.u8()? {
+            0xFF => None,
+            key if key < 16 => Some(key),
+            _ => return Err(SaveStateError::InvalidData("pressed key")),
+        };
+        let font_address = reader.u16()?;
+        if font_address as usize + FONT.len() + BIG_FONT.len() > memory_size {
+            return Err(SaveStateError::InvalidData("font address"));
+        }
+        let random = Random::read(&mut reader)?;
+        let timing = match reader.u8()? {
+            0 => Timing::Fixed,
+            1 => Timing::CosmacVip,
+            _ => return Err(SaveStateError::InvalidData("timing")),
+        };
+        let cycles = reader.u64()?;
+        let frame_cycles = reader.u32()?;
+
+        self.instruction_set = instruction_set;
+        self.quirks = quirks;
+        self.memory = memory;
+        self.hires = hires;
+        self.planes = planes;
+        self.display = display;
+        self.pc = pc;
+        self.index = index;
+        self.stack = stack;
+        self.sp = sp;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.registers = registers;
+        for (i, state) in self.keypad.iter_mut().enumerate() {
+            *state = keypad_bits >> i & 1 == 1;
+        }
+        self.rpl_flags = rpl_flags;
+        self.audio_pattern = has_audio_pattern.then_some(audio_pattern);
+        self.pitch = pitch;
+        self.halted = halted;
+        self.vblank = vblank;
+        self.pressed_key = pressed_key;
+        self.font_address = font_address;
+        self.random = random;
+        self.timing = timing;
+        self.cycles = cycles;
+        self.frame_cycles = frame_cycles;
+        Ok(())
+    }
+
+    /// Width of the active display area, 128 in high resolution mode and 64 otherwise
+    pub fn display_width(&self) -> usize {
+        if self.hires { DISPLAY_WIDTH } else { DISPLAY_WIDTH / 2 }
+    }
+
+    /// Height of the active display area, 64 in high resolution mode and 32 otherwise
+    pub fn display_height(&self) -> usize {
+        if self.hires { DISPLAY_HEIGHT } else { DISPLAY_HEIGHT / 2 }
+    }
+
+    /// The random number generator, whose seed reproduces the run
+    pub fn random(&self) -> &Random {
+        &self.random
+    }
+
+    /// Whether the program has exited through the SUPER-CHIP 00FD instruction
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn set_index(&mut self, index: u16) {
+        self.index = index;
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn set_register(&mut self, register: usize, value: u8) {
+        self.registers[register] = value;
+    }
+
+    /// The number of instructions run since the emulator was built, including any that failed. Loading a state doesn't
+    /// change it
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The return addresses currently on the stack, innermost call last
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp]
+    }
+
+    pub fn memory_size(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// The whole memory, read without it being recorded as accesses by the program
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Reads a byte of memory without it being recorded as an access by the program
+    pub fn peek(&self, address: u16) -> u8 {
+        self.memory[address as usize % self.memory.len()]
+    }
+
+    /// Writes a byte of memory without it being recorded as an access by the program
+    pub fn poke(&mut self, address: u16, value: u8) {
+        let length = self.memory.len();
+        self.memory[address as usize % length] = value;
+    }
+
+    /// The two byte opcode stored at the given address
+    pub fn opcode_at(&self, address: u16) -> u16 {
+        (self.peek(address) as u16) << 8 | self.peek(address.wrapping_add(1)) as u16
+    }
+
+    /// Enables recording the memory reads and writes made by each step, for watchpoints and memory views
+    pub fn set_record_memory_accesses(&mut self, record: bool) {
+        self.record_memory_accesses = record;
+        self.memory_accesses.clear();
+    }
+
+    pub fn records_memory_accesses(&self) -> bool {
+        self.record_memory_accesses
+    }
+
+    /// The memory accesses made by the last step, if recording is enabled
+    pub fn memory_accesses(&self) -> &[MemoryAccess] {
+        &self.memory_accesses
+    }
+
+    /// What should be heard right now, given the sound timer and any XO-CHIP audio pattern
+    pub fn sound(&self) -> Sound {
+        match self.audio_pattern {
+            _ if self.sound_timer == 0 => Sound::Silent,
+            Some(pattern) => Sound::Pattern { pattern, pitch: self.pitch },
+            None => Sound::Tone,
+        }
+    }
 
     pub fn tick_clock(&mut self) {
+        self.vblank = true;
+        if self.timing == Timing::CosmacVip {
+            // Cycles run past the end of the frame are carried into the next one
+            self.frame_cycles = self.frame_cycles.saturating_sub(CYCLES_PER_FRAME) + INTERRUPT_CYCLES;
+            self.cycles += INTERRUPT_CYCLES as u64;
+        }
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
@@ -134,22 +628,80 @@ impl Emulator {
     }
 
     pub fn step(&mut self) -> Result<bool, EmulatorError> {
+        self.memory_accesses.clear();
+        if self.halted {
+            return Ok(false);
+        }
+        let pc = self.pc;
         let opcode = self.fetch_opcode()?;
-        self.execute_opcode(opcode)
+        self.steps += 1;
+        if self.timing == Timing::Fixed {
+            return self.execute_opcode(opcode);
+        }
+        let (registers, index) = (self.registers, self.index);
+        let result = self.execute_opcode(opcode);
+        self.charge_cycles(opcode, pc, &registers, index);
+        result
+    }
+
+    // Charges the machine cycles of an instruction under the COSMAC VIP timing model
+    fn charge_cycles(&mut self, opcode: u16, pc: u16, registers: &[u8; 16], index: u16) {
+        let skipped = matches!(opcode >> 12, 0x3 | 0x4 | 0x5 | 0x9 | 0xE) && self.pc != pc.wrapping_add(2);
+        let mut cycles = timing::instruction_cycles(opcode, registers, index, skipped);
+        // A draw waiting for the vertical blank idles until the interrupt
+        if opcode >> 12 == 0xD && self.pc == pc {
+            cycles = cycles.max(CYCLES_PER_FRAME.saturating_sub(self.frame_cycles));
+        }
+        self.cycles += cycles as u64;
+        self.frame_cycles += cycles;
     }
 
     fn fetch_opcode(&mut self) -> Result<u16, EmulatorError> {
-        if self.pc as usize >= self.memory.len() {
+        if self.pc as usize + 1 >= self.memory.len() {
             return Err(EmulatorError::PcOutOfBounds());
         }
-        let opcode = (self.memory[self.pc as usize] as u16) << 8 |
-            (self.memory[self.pc as usize + 1] as u16);
-        self.pc += 2;
+        let opcode = self.peek_opcode();
+        // The program counter wraps around the end of a 64 KiB address space
+        self.pc = self.pc.wrapping_add(2);
         Ok(opcode)
     }
 
+    fn peek_opcode(&self) -> u16 {
+        self.opcode_at(self.pc)
+    }
+
+    // Memory accesses wrap around the end of the address space
+    fn read_memory(&mut self, address: usize) -> u8 {
+        let address = address % self.memory.len();
+        if self.record_memory_accesses {
+            self.memory_accesses.push(MemoryAccess::Read(address as u16));
+        }
+        self.memory[address]
+    }
+
+    fn write_memory(&mut self, address: usize, value: u8) {
+        let address = address % self.memory.len();
+        if self.record_memory_accesses {
+            self.memory_accesses.push(MemoryAccess::Write(address as u16));
+        }
+        self.memory[address] = value;
+    }
+
+    fn skip_next_instruction(&mut self) {
+        // The XO-CHIP long index load is 4 bytes long, so skipping over it must skip both words
+        if self.instruction_set == InstructionSet::XoChip && self.peek_opcode() == 0xF000 {
+            self.pc = self.pc.wrapping_add(4);
+        } else {
+            self.pc = self.pc.wrapping_add(2);
+        }
+    }
+
     fn execute_opcode(&mut self, opcode: u16) -> Result<bool, EmulatorError> {
         let instruction = Instruction::from_opcode(opcode);
+        // Only opcodes in the opcode table for the current instruction set are valid
+        if opcodes::decode(opcode, self.instruction_set).is_none() {
+            return Err(EmulatorError::InvalidInstruction(instruction));
+        }
 
         match instruction.operation {
             0x00 => self.operation_0(instruction),
@@ -172,14 +724,27 @@ impl Emulator {
         }
     }
 
+    fn supports_super_chip(&self) -> bool {
+        self.instruction_set != InstructionSet::Chip8
+    }
+
+    fn supports_xo_chip(&self) -> bool {
+        self.instruction_set == InstructionSet::XoChip
+    }
+
+    // Clears the selected bitplanes
+    fn clear_display(&mut self) {
+        for row in self.display.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= !self.planes;
+            }
+        }
+    }
+
     fn operation_0(&mut self, instruction: Instruction) -> Result<bool, EmulatorError> {
         match instruction.nnn {
             0x0E0 => { // Clear screen
-                for row in self.display.iter_mut() {
-                    for pixel in row.iter_mut() {
-                        *pixel = false;
-                    }
-                }
+                self.clear_display();
             }
             0x0EE => { // Return
                 if self.sp == 0 {
@@ -188,6 +753,40 @@ impl Emulator {
                 self.sp -= 1;
                 self.pc = self.stack[self.sp];
             }
+            0x0C0..=0x0CF if self.supports_super_chip() => { // Scroll display down by n pixels
+                self.scroll_down(instruction.n as usize);
+                return Ok(true);
+            }
+            0x0D0..=0x0DF if self.supports_xo_chip() => { // Scroll display up by n pixels
+                self.scroll_up(instruction.n as usize);
+                return Ok(true);
+            }
+            0x0FB if self.supports_super_chip() => { // Scroll display right by 4 pixels
+                self.scroll_right(4);
+                return Ok(true);
+            }
+            0x0FC if self.supports_super_chip() => { // Scroll display left by 4 pixels
+                self.scroll_left(4);
+                return Ok(true);
+            }
+            0x0FD if self.supports_super_chip() => { // Exit the interpreter
+                self.halted = true;
+            }
+            // SUPER-CHIP 1.1 keeps the display when switching resolution, while XO-CHIP clears it
+            0x0FE if self.supports_super_chip() => { // Switch to low resolution mode
+                self.hires = false;
+                if self.supports_xo_chip() {
+                    self.display = [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+                }
+                return Ok(true);
+            }
+            0x0FF if self.supports_super_chip() => { // Switch to high resolution mode
+                self.hires = true;
+                if self.supports_xo_chip() {
+                    self.display = [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+                }
+                return Ok(true);
+            }
             _ => {
                 return Err(EmulatorError::InvalidInstruction(instruction));
             }
@@ -195,6 +794,49 @@ impl Emulator {
         Ok(false)
     }
 
+    // Scrolling only moves the selected bitplanes
+    fn scroll_down(&mut self, amount: usize) {
+        let (width, height) = (self.display_width(), self.display_height());
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let source = if y >= amount { self.display[y - amount][x] } else { 0 };
+                self.display[y][x] = (self.display[y][x] & !self.planes) | (source & self.planes);
+            }
+        }
+    }
+
+    fn scroll_up(&mut self, amount: usize) {
+        let (width, height) = (self.display_width(), self.display_height());
+        for y in 0..height {
+            for x in 0..width {
+                let source = if y + amount < height { self.display[y + amount][x] } else { 0 };
+                self.display[y][x] = (self.display[y][x] & !self.planes) | (source & self.planes);
+            }
+        }
+    }
+
+    fn scroll_right(&mut self, amount: usize) {
+        let (width, height) = (self.display_width(), self.display_height());
+        let planes = self.planes;
+        for row in self.display.iter_mut().take(height) {
+            for x in (0..width).rev() {
+                let source = if x >= amount { row[x - amount] } else { 0 };
+                row[x] = (row[x] & !planes) | (source & planes);
+            }
+        }
+    }
+
+    fn scroll_left(&mut self, amount: usize) {
+        let (width, height) = (self.display_width(), self.display_height());
+        let planes = self.planes;
+        for row in self.display.iter_mut().take(height) {
+            for x in 0..width {
+                let source = if x + amount < width { row[x + amount] } else { 0 };
+                row[x] = (row[x] & !planes) | (source & planes);
+            }
+        }
+    }
+
     fn operation_1(&mut self, instruction: Instruction) -> Result<bool, EmulatorError> {
         // Jump to address NNN
         self.pc = instruction.nnn;
@@ -203,11 +845,11 @@ impl Emulator {
 
     fn operation_2(&mut self, instruction: Instruction) -> Result<bool, EmulatorError> {
         // Call subroutine at NNN
-        self.stack[self.sp] = self.pc;
-        self.sp += 1;
         if self.sp == self.stack.len() {
             return Err(EmulatorError::StackOverflow);
         }
+        self.stack[self.sp] = self.pc;
+        self.sp += 1;
         self.pc = instruction.nnn;
         Ok(false)
     }
@@ -215,7 +857,7 @@ impl Emulator {
     fn operation_3(&mut self, instruction: Instruction) -> Result<bool, EmulatorError> {
         // Skip next instruction if VX == NN
         if self.registers[instruction.x as usize] == instruction.nn {
-            self.pc += 2;
+            self.skip_next_instruction();
         }
         Ok(false)
     }
@@ -223,19 +865,44 @@ impl Emulator {
     fn operation_4(&mut self, instruction: Instruction) -> Result<bool, EmulatorError> {
         // Skip next instruction if VX != NN
         if self.registers[instruction.x as usize] != instruction.nn {
-            self.pc += 2;
+            self.skip_next_instruction();
         }
         Ok(false)
     }
 
     fn operation_5(&mut self, instruction: Instruction) -> Result<bool, EmulatorError> {
-        // Skip next instruction if VX == VY
-        if self.registers[instruction.x as usize] == self.registers[instruction.y as usize] {
-            self.pc += 2;
+        match instruction.n {
+            0x0 => { // Skip next instruction if VX == VY
+                if self.registers[instruction.x as usize] == self.registers[instruction.y as usize] {
+                    self.skip_next_instruction();
+                }
+            }
+            0x2 if self.supports_xo_chip() => { // Store registers VX through VY in memory starting at location I
+                for (offset, register) in Self::register_range(instruction.x, instruction.y).enumerate() {
+                    self.write_memory(self.index as usize + offset, self.registers[register]);
+                }
+            }
+            0x3 if self.supports_xo_chip() => { // Load registers VX through VY from memory starting at location I
+                for (offset, register) in Self::register_range(instruction.x, instruction.y).enumerate() {
+                    self.registers[register] = self.read_memory(self.index as usize + offset);
+                }
+            }
+            _ => {
+                return Err(EmulatorError::InvalidInstruction(instruction));
+            }
         }
         Ok(false)
     }
 
+    // Registers from x to y inclusive, in descending order if y is smaller than x
+    fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = usize>> {
+        if x <= y {
+            Box::new(x as usize..=y as usize)
+        } else {
+            Box::new((y as usize..=x as usize).rev())
+        }
+    }
+
     fn operation_6(&mut self, instruction: Instruction) -> Result<bool, EmulatorError> {
         // Load value into register Vx
         self.registers[instruction.x as usize] = instruction.nn;
@@ -255,12 +922,21 @@ impl Emulator {
             }
             0x1 => { // Or Vx with Vy
                 self.registers[instruction.x as usize] |= self.registers[instruction.y as usize];
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
             }
             0x2 => { // And Vx with Vy
                 self.registers[instruction.x as usize] &= self.registers[instruction.y as usize];
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
             }
             0x3 => { // Xor Vx with Vy
                 self.registers[instruction.x as usize] ^= self.registers[instruction.y as usize];
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
             }
             0x4 => { // Add Vx to Vy
                 let (result, carry) = self.registers[instruction.x as usize].overflowing_add(self.registers[instruction.y as usize]);
@@ -273,7 +949,7 @@ impl Emulator {
                 self.registers[0xF] = !borrow as u8;
             }
             0x6 => { // Shift Vx right by 1
-                if self.shift_sets_vx {
+                if self.quirks.shift_sets_vx {
                     self.registers[instruction.x as usize] = self.registers[instruction.y as usize];
                 }
                 self.registers[0xF] = self.registers[instruction.x as usize] & 0x1;
@@ -285,7 +961,7 @@ impl Emulator {
                 self.registers[0xF] = !borrow as u8;
             }
             0xE => { // Shift Vx left by 1
-                if self.shift_sets_vx {
+                if self.quirks.shift_sets_vx {
                     self.registers[instruction.x as usize] = self.registers[instruction.y as usize];
                 }
                 self.registers[0xF] = (self.registers[instruction.x as usize] >> 7) & 0x1;
@@ -301,7 +977,7 @@ impl Emulator {
     fn operation_9(&mut self, instruction: Instruction) -> Result<bool, EmulatorError> {
         // Skip next instruction if Vx != Vy
         if self.registers[instruction.x as usize] != self.registers[instruction.y as usize] {
-            self.pc += 2;
+            self.skip_next_instruction();
         }
         Ok(false)
     }
@@ -314,7 +990,7 @@ impl Emulator {
 
     fn operation_b(&mut self, instruction: Instruction) -> Result<bool, EmulatorError> {
         // Jump to address NNN + V0 unless the chip-48 bug is being emulated
-        if self.jump_with_offset_bug_emulation {
+        if self.quirks.jump_with_offset_bug_emulation {
             self.pc = instruction.nnn + self.registers[instruction.x as usize] as u16;
         } else {
             self.pc = instruction.nnn + self.registers[0] as u16;
@@ -324,40 +1000,75 @@ impl Emulator {
 
     fn operation_c(&mut self, instruction: Instruction) -> Result<bool, EmulatorError> {
         // Load random number into register Vx
-        self.registers[instruction.x as usize] = random::<u8>() & instruction.nn;
+        self.registers[instruction.x as usize] = self.random.next_byte() & instruction.nn;
         Ok(false)
     }
 
     fn operation_d(&mut self, instruction: Instruction) -> Result<bool, EmulatorError> {
         // Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
+        // On SUPER-CHIP, a height of 0 draws a 16x16 sprite made of 32 bytes instead, or an 8x16
+        // sprite in low resolution mode as SUPER-CHIP 1.1 does, where XO-CHIP still draws 16x16.
+        // On XO-CHIP, the sprite is drawn to each selected bitplane in turn, with the data for
+        // each plane following the previous one in memory.
+        let (sprite_width, sprite_height) = match instruction.n {
+            0 if self.hires || self.supports_xo_chip() => (16, 16),
+            0 if self.supports_super_chip() => (8, 16),
+            n => (8, n as usize),
+        };
+        let bytes_per_row = sprite_width / 8;
+        let (width, height) = (self.display_width(), self.display_height());
+
+        // Wait for the vertical blank by retrying the instruction until the next clock tick
+        if self.quirks.display_wait {
+            if !self.vblank {
+                self.pc = self.pc.wrapping_sub(2);
+                return Ok(false);
+            }
+            self.vblank = false;
+        }
 
         // Clear VF
         self.registers[0xF] = 0;
 
-        let mut y = self.registers[instruction.y as usize] % self.display.len() as u8;
-        // For each row of sprite
-        for row in 0..instruction.n {
-            let mut x = self.registers[instruction.x as usize] % self.display[0].len() as u8;
-            let sprite_row = self.memory[(self.index + row as u16) as usize];
-            // For each pixel in row
-            for col in 0..8 {
-                let sprite_pixel = (sprite_row >> (7 - col)) & 1 == 1;
-                let display_pixel = self.display[y as usize][x as usize];
-                // Check for collision
-                if sprite_pixel && display_pixel {
-                    self.registers[0xF] = 1;
+        let start_x = self.registers[instruction.x as usize] as usize % width;
+        let start_y = self.registers[instruction.y as usize] as usize % height;
+        let mut address = self.index as usize;
+        let planes = self.planes;
+        for plane in (0..2).map(|plane| 1u8 << plane).filter(|plane| planes & plane != 0) {
+            // For each row of sprite, clipping at the bottom of the screen or wrapping around
+            for row in 0..sprite_height {
+                let mut y = start_y + row;
+                if y >= height {
+                    if self.quirks.clip_sprites {
+                        break;
+                    }
+                    y %= height;
                 }
-                // Xor display pixel
-                self.display[y as usize][x as usize] ^= sprite_pixel;
-                x += 1;
-                if x >= self.display[0].len() as u8 {
-                    break;
+                let mut sprite_row = 0u16;
+                for byte in 0..bytes_per_row {
+                    sprite_row = sprite_row << 8 | self.read_memory(address + row * bytes_per_row + byte) as u16;
+                }
+                // For each pixel in row, clipping at the right of the screen or wrapping around
+                for col in 0..sprite_width {
+                    let mut x = start_x + col;
+                    if x >= width {
+                        if self.quirks.clip_sprites {
+                            break;
+                        }
+                        x %= width;
+                    }
+                    if (sprite_row >> (sprite_width - 1 - col)) & 1 == 0 {
+                        continue;
+                    }
+                    // Check for collision
+                    if self.display[y][x] & plane != 0 {
+                        self.registers[0xF] = 1;
+                    }
+                    // Xor display pixel
+                    self.display[y][x] ^= plane;
                 }
             }
-            y += 1;
-            if y >= self.display.len() as u8 {
-                break;
-            }
+            address += sprite_height * bytes_per_row;
         }
         Ok(true)
     }
@@ -369,7 +1080,7 @@ impl Emulator {
                     return Err(EmulatorError::InvalidInstruction(instruction));
                 }
                 if self.keypad[self.registers[instruction.x as usize] as usize] {
-                    self.pc += 2;
+                    self.skip_next_instruction();
                 }
             }
             0xA1 => { // Skip next instruction if key with value Vx is not pressed
@@ -377,7 +1088,7 @@ impl Emulator {
                     return Err(EmulatorError::InvalidInstruction(instruction));
                 }
                 if !self.keypad[self.registers[instruction.x as usize] as usize] {
-                    self.pc += 2;
+                    self.skip_next_instruction();
                 }
             }
             _ => {
@@ -389,6 +1100,26 @@ impl Emulator {
 
     fn operation_f(&mut self, instruction: Instruction) -> Result<bool, EmulatorError> {
         match instruction.nn {
+            0x00 if instruction.x == 0 && self.supports_xo_chip() => {
+                // Load the 16-bit address in the following word into I
+                self.index = self.fetch_opcode()?;
+            }
+            0x01 if self.supports_xo_chip() => {
+                // Select the bitplanes affected by drawing, clearing and scrolling
+                self.planes = instruction.x & 0x3;
+            }
+            0x02 if instruction.x == 0 && self.supports_xo_chip() => {
+                // Load the 16 byte audio pattern buffer from memory starting at location I
+                let mut pattern = [0; 16];
+                for (i, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.read_memory(self.index as usize + i);
+                }
+                self.audio_pattern = Some(pattern);
+            }
+            0x3A if self.supports_xo_chip() => {
+                // Set the audio pattern playback pitch to Vx
+                self.pitch = self.registers[instruction.x as usize];
+            }
             0x07 => { // Load Vx with delay timer value
                 self.registers[instruction.x as usize] = self.delay_timer;
             }
@@ -399,60 +1130,91 @@ impl Emulator {
                 self.sound_timer = self.registers[instruction.x as usize];
             }
             0x1e => { // Add Vx to I
-                self.index += self.registers[instruction.x as usize] as u16;
-                // Check for overflow. Original cosmac emulator does not check for overflow
-                // however some interpreters do and at least one game is known to rely on this.
-                // No known games relies on this not happening, so we check for overflow
-                if self.index > 0xFFF {
-                    self.registers[0xF] = 1;
-                    self.index &= 0xFFF;
-                } else {
-                    self.registers[0xF] = 0;
+                let index = self.index as usize + self.registers[instruction.x as usize] as usize;
+                // Check for overflow, if the quirk is enabled
+                if self.quirks.index_overflow_sets_vf {
+                    self.registers[0xF] = (index >= self.memory.len()) as u8;
                 }
+                self.index = (index % self.memory.len()) as u16;
             }
             0x0A => {
                 // Wait for a key press and store the value of the key in Vx
                 let mut key_pressed = false;
-                for (index, state) in self.keypad.iter().enumerate() {
-                    if *state {
-                        self.registers[instruction.x as usize] = index as u8;
-                        key_pressed = true;
-                        break;
+                if self.quirks.key_wait_on_release {
+                    // Remember the pressed key and only complete once it is released
+                    match self.pressed_key {
+                        Some(key) if !self.keypad[key as usize] => {
+                            self.registers[instruction.x as usize] = key;
+                            self.pressed_key = None;
+                            key_pressed = true;
+                        }
+                        Some(_) => {}
+                        None => {
+                            self.pressed_key = self.keypad.iter().position(|state| *state).map(|key| key as u8);
+                        }
+                    }
+                } else {
+                    for (index, state) in self.keypad.iter().enumerate() {
+                        if *state {
+                            self.registers[instruction.x as usize] = index as u8;
+                            key_pressed = true;
+                            break;
+                        }
                     }
                 }
                 if !key_pressed {
-                    self.pc -= 2;
+                    self.pc = self.pc.wrapping_sub(2);
                 }
             }
             0x29 => {
                 // Load location of sprite for digit Vx into I
-                self.index = ((self.registers[instruction.x as usize] as u16 & 0xF) * 5) + FONT_BASE_ADDRESS;
+                self.index = ((self.registers[instruction.x as usize] as u16 & 0xF) * 5) + self.font_address;
+            }
+            0x30 if self.supports_super_chip() => {
+                // Load location of big 8x10 sprite for digit Vx into I
+                self.index = ((self.registers[instruction.x as usize] as u16 & 0xF) * 10) + self.font_address + FONT.len() as u16;
             }
             0x33 => {
                 // Store BCD representation of Vx in memory locations I, I+1, and I+2
                 let mut value = self.registers[instruction.x as usize];
-                self.memory[self.index as usize] = value / 100;
+                self.write_memory(self.index as usize, value / 100);
                 value %= 100;
-                self.memory[(self.index + 1) as usize] = value / 10;
+                self.write_memory(self.index as usize + 1, value / 10);
                 value %= 10;
-                self.memory[(self.index + 2) as usize] = value;
+                self.write_memory(self.index as usize + 2, value);
             }
             0x55 => {
                 // Store registers V0 through Vx in memory starting at location I
                 for i in 0..instruction.x + 1 {
-                    self.memory[(self.index + i as u16) as usize] = self.registers[i as usize];
+                    self.write_memory(self.index as usize + i as usize, self.registers[i as usize]);
                 }
-                if self.increment_i_on_store_and_load {
-                    self.index += instruction.x as u16 + 1;
+                if self.quirks.increment_i_on_store_and_load {
+                    self.index = self.index.wrapping_add(instruction.x as u16 + 1);
                 }
             }
             0x65 => {
                 // Load registers V0 through Vx from memory starting at location I
                 for i in 0..instruction.x + 1 {
-                    self.registers[i as usize] = self.memory[(self.index + i as u16) as usize];
+                    self.registers[i as usize] = self.read_memory(self.index as usize + i as usize);
                 }
-                if self.increment_i_on_store_and_load {
-                    self.index += instruction.x as u16 + 1;
+                if self.quirks.increment_i_on_store_and_load {
+                    self.index = self.index.wrapping_add(instruction.x as u16 + 1);
+                }
+            }
+            0x75 | 0x85 if instruction.x > 7 && !self.supports_xo_chip() => {
+                // SUPER-CHIP only has 8 RPL user flags, while XO-CHIP has 16
+                return Err(EmulatorError::InvalidInstruction(instruction));
+            }
+            0x75 if self.supports_super_chip() => {
+                // Store registers V0 through Vx in the RPL user flags
+                for i in 0..=instruction.x as usize {
+                    self.rpl_flags[i] = self.registers[i];
+                }
+            }
+            0x85 if self.supports_super_chip() => {
+                // Load registers V0 through Vx from the RPL user flags
+                for i in 0..=instruction.x as usize {
+                    self.registers[i] = self.rpl_flags[i];
                 }
             }
             _ => {
@@ -461,4 +1223,187 @@ impl Emulator {
         }
         Ok(false)
     }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stack_depth_limits() {
+        assert!(matches!(Emulator::builder(&[]).stack_depth(0).build(), Err(EmulatorError::InvalidStackDepth(0))));
+        assert!(matches!(Emulator::builder(&[]).stack_depth(0x10000).build(), Err(EmulatorError::InvalidStackDepth(0x10000))));
+
+        // The deepest stack survives a save state
+        let mut emulator = Emulator::builder(&[0x22, 0x00]).stack_depth(0xFFFF).build().unwrap();
+        emulator.step().unwrap();
+        let mut loaded = Emulator::builder(&[0x22, 0x00]).build().unwrap();
+        loaded.load_state(&emulator.save_state()).unwrap();
+        assert_eq!(loaded.stack(), [0x202]);
+        assert_eq!(loaded.save_state(), emulator.save_state());
+    }
+
+    fn run(instruction_set: InstructionSet, program: &[u8], steps: usize) -> Emulator {
+        let mut emulator = Emulator::builder(program).instruction_set(instruction_set).build().unwrap();
+        for _ in 0..steps {
+            emulator.step().unwrap();
+        }
+        emulator
+    }
+
+    #[test]
+    fn xo_chip_bitplanes() {
+        let program = [
+            0xA2, 0x20, 0xF2, 0x01, 0xD0, 0x01, // Draws a row to the second plane
+            0xF3, 0x01, 0xA2, 0x21, 0xD0, 0x01, // Draws a row to each plane, the first then the second
+            0xF1, 0x01, 0x00, 0xE0, // Clears the first plane
+        ];
+        let mut program = program.to_vec();
+        program.resize(0x20, 0);
+        program.extend([0xF0, 0xC0, 0x30]);
+
+        let emulator = run(InstructionSet::XoChip, &program, 3);
+        assert_eq!(emulator.display[0][..6], [2, 2, 2, 2, 0, 0]);
+        assert_eq!(emulator.registers()[0xF], 0);
+        let emulator = run(InstructionSet::XoChip, &program, 6);
+        assert_eq!(emulator.display[0][..6], [3, 3, 0, 0, 0, 0]);
+        assert_eq!(emulator.registers()[0xF], 1);
+        let emulator = run(InstructionSet::XoChip, &program, 8);
+        assert_eq!(emulator.display[0][..6], [2, 2, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn xo_chip_register_ranges() {
+        let program = [
+            0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xA3, 0x00, 0x50, 0x22, // Stores V0 to V2 at 0x300
+            0xA3, 0x10, 0x52, 0x02, // Stores V2 down to V0 at 0x310
+            0xA3, 0x00, 0x53, 0x53, // Loads V3 to V5 from 0x300
+        ];
+        let emulator = run(InstructionSet::XoChip, &program, 9);
+        assert_eq!(emulator.memory()[0x300..0x303], [1, 2, 3]);
+        assert_eq!(emulator.memory()[0x310..0x313], [3, 2, 1]);
+        assert_eq!(emulator.registers()[..6], [1, 2, 3, 1, 2, 3]);
+        assert_eq!(emulator.index(), 0x300);
+        assert!(Emulator::builder(&[0x50, 0x12]).build().unwrap().step().is_err());
+    }
+
+    #[test]
+    fn skips_over_long_index_load() {
+        // Skips the 4 bytes of F000 NNNN on XO-CHIP, but only 2 bytes before it
+        let program = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x60, 0x01];
+        assert_eq!(run(InstructionSet::XoChip, &program, 1).pc(), 0x206);
+        assert_eq!(run(InstructionSet::SuperChip, &program, 1).pc(), 0x204);
+        let emulator = run(InstructionSet::XoChip, &program[2..], 1);
+        assert_eq!((emulator.pc(), emulator.index()), (0x204, 0x1234));
+    }
+
+    #[test]
+    fn pc_wraps_around_memory() {
+        let mut emulator = Emulator::builder(&[]).instruction_set(InstructionSet::XoChip).build().unwrap();
+        assert_eq!(emulator.memory_size(), 0x10000);
+        emulator.poke(0xFFFC, 0xF0);
+        emulator.poke(0xFFFD, 0x00);
+        emulator.poke(0xFFFE, 0x12);
+        emulator.poke(0xFFFF, 0x34);
+        emulator.set_pc(0xFFFC);
+        emulator.step().unwrap();
+        assert_eq!((emulator.pc(), emulator.index()), (0x0000, 0x1234));
+
+        // The address of a long index load at the end of memory is at the start
+        emulator.poke(0xFFFE, 0xF0);
+        emulator.poke(0xFFFF, 0x00);
+        emulator.set_pc(0xFFFE);
+        emulator.step().unwrap();
+        assert_eq!((emulator.pc(), emulator.index()), (0x0002, emulator.opcode_at(0)));
+
+        // A skip from the end of memory lands past the start
+        emulator.poke(0xFFFE, 0x30);
+        emulator.poke(0xFFFF, 0x00);
+        emulator.set_register(0, 0);
+        emulator.set_pc(0xFFFE);
+        emulator.step().unwrap();
+        assert_eq!(emulator.pc(), 0x0002);
+    }
+
+    #[test]
+    fn super_chip_resolution() {
+        // Draws a pixel, then switches to high resolution and back
+        let program = [0xA2, 0x0A, 0xD0, 0x01, 0x00, 0xFF, 0x00, 0xFE, 0x12, 0x08, 0x80];
+        let emulator = run(InstructionSet::SuperChip, &program, 3);
+        assert_eq!((emulator.display_width(), emulator.display_height()), (128, 64));
+        // SUPER-CHIP 1.1 keeps the display
+        assert_eq!(emulator.display[0][0], 1);
+        let emulator = run(InstructionSet::SuperChip, &program, 4);
+        assert_eq!((emulator.display_width(), emulator.display_height()), (64, 32));
+        assert_eq!(emulator.display[0][0], 1);
+        // XO-CHIP clears it
+        assert_eq!(run(InstructionSet::XoChip, &program, 3).display[0][0], 0);
+        assert!(Emulator::builder(&program[4..]).build().unwrap().step().is_err());
+    }
+
+    #[test]
+    fn large_sprites() {
+        // Draws a 16 row sprite of alternating full and empty rows in high and in low resolution
+        let mut program = vec![0xA2, 0x10, 0x00, 0xFF, 0xD0, 0x00, 0x00, 0xFE, 0x00, 0xE0, 0xD0, 0x00, 0x12, 0x0C];
+        program.resize(0x10, 0);
+        program.extend([0xFF, 0xFF, 0x00, 0x00].repeat(8));
+
+        let emulator = run(InstructionSet::SuperChip, &program, 3);
+        for (y, row) in emulator.display[..17].iter().enumerate() {
+            let expected = if y < 16 && y % 2 == 0 { 1 } else { 0 };
+            assert!(row[..16].iter().all(|pixel| *pixel == expected), "row {}", y);
+            assert_eq!(row[16], 0);
+        }
+        // Low resolution draws the first byte of each of 16 rows as 8 pixels
+        let emulator = run(InstructionSet::SuperChip, &program, 6);
+        for (y, row) in emulator.display[..17].iter().enumerate() {
+            let expected = [1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 0][y];
+            assert!(row[..8].iter().all(|pixel| *pixel == expected), "row {}", y);
+            assert_eq!(row[8], 0);
+        }
+        // XO-CHIP draws 16x16 in low resolution too
+        let emulator = run(InstructionSet::XoChip, &program, 6);
+        assert!(emulator.display[0][..16].iter().all(|pixel| *pixel == 1));
+        assert_eq!(emulator.display[1][0], 0);
+    }
+
+    // The pixels set in low resolution, as (x, y)
+    fn lit(emulator: &Emulator) -> Vec<(usize, usize)> {
+        let pixels = (0..32).flat_map(|y| (0..64).map(move |x| (x, y)));
+        pixels.filter(|&(x, y)| emulator.display[y][x] != 0).collect()
+    }
+
+    #[test]
+    fn scrolling() {
+        let mut emulator = Emulator::builder(&[0x00, 0xC3, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFC, 0x00, 0xD1])
+            .instruction_set(InstructionSet::XoChip).build().unwrap();
+        emulator.display[0][0] = 1;
+        emulator.display[0][63] = 1;
+        let mut step = || {
+            emulator.step().unwrap();
+            lit(&emulator)
+        };
+        // Down by 3, then right by 4, which scrolls the pixel at the right edge out, then left twice
+        assert_eq!(step(), [(0, 3), (63, 3)]);
+        assert_eq!(step(), [(4, 3)]);
+        assert_eq!(step(), [(0, 3)]);
+        assert_eq!(step(), []);
+        emulator.display[3][10] = 1;
+        emulator.step().unwrap();
+        assert_eq!(lit(&emulator), [(10, 2)]);
+    }
+
+    #[test]
+    fn rpl_flags() {
+        // Stores V0 to V7 in the flags, clears them, and loads them back
+        let program = [0x60, 0x01, 0x67, 0x08, 0xF7, 0x75, 0x60, 0x00, 0x67, 0x00, 0xF7, 0x85, 0xFF, 0x75];
+        let mut emulator = run(InstructionSet::SuperChip, &program, 6);
+        assert_eq!((emulator.registers()[0], emulator.registers()[7]), (1, 8));
+        // SUPER-CHIP only has 8 flags
+        assert!(matches!(emulator.step(), Err(EmulatorError::InvalidInstruction(_))));
+        let mut emulator = run(InstructionSet::XoChip, &program, 6);
+        emulator.step().unwrap();
+        assert!(Emulator::builder(&program[4..]).build().unwrap().step().is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,176 @@
+use crate::display::{Frame, Palette};
+use crate::emulator::{DISPLAY_HEIGHT, DISPLAY_WIDTH, Emulator};
+
+
+/// The highest persistence, above which pixels would take too long to fade
+pub const MAX_PERSISTENCE: f32 = 0.95;
+
+/// Turns the display into the frame frontends present, in the colours of a palette. Optional filters reduce the flicker
+/// of CHIP-8 programs, which erase sprites by drawing them again and so often show them missing for a frame:
+///
+/// - Persistence keeps pixels glowing after they turn off like the phosphor of old monitors, fading by the decay every
+///   frame
+/// - Blending averages every frame with the one before, so pixels that flicker show at half brightness
+/// - Drawing only on vblank shows the display at the end of a frame only once the program finished drawing it, which is
+///   when the last change to the display erased no pixels, or nothing was changed in the whole frame. Until then the
+///   last finished display stays on screen, so a sprite that is erased in one frame and drawn again in the next doesn't
+///   go missing
+///
+/// The filters work one emulated frame at a time, so [`DisplayFilter::end_frame`] is called at the end of every frame and
+/// [`DisplayFilter::before_step`] before every instruction.
+pub struct DisplayFilter {
+    palette: Palette,
+    persistence: f32,
+    blend: bool,
+    vblank: bool,
+    // The last display the program finished drawing, along with its width and height
+    finished: Option<([[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT], usize, usize)>,
+    // The address of a sprite drawn by the last instruction, whose collision flag tells whether it erased pixels
+    drawn_at: Option<u16>,
+    // Whether the display changed this frame, and whether the last change erased pixels
+    changed: bool,
+    erased: bool,
+    // The colours of the previous frame before filtering, to blend with
+    previous: Option<Vec<[f32; 3]>>,
+    // The colours presented last, kept unrounded so pixels fade smoothly
+    output: Vec<[f32; 3]>,
+    frame: Frame,
+}
+
+impl DisplayFilter {
+    pub fn new(palette: Palette) -> DisplayFilter {
+        DisplayFilter {
+            palette,
+            persistence: 0.0,
+            blend: false,
+            vblank: false,
+            finished: None,
+            drawn_at: None,
+            changed: false,
+            erased: false,
+            previous: None,
+            output: Vec::new(),
+            frame: Frame { width: 0, height: 0, pixels: Vec::new() },
+        }
+    }
+
+    /// Keeps pixels glowing after they turn off, keeping the given share of their brightness every frame. 0 turns
+    /// persistence off, and the decay is limited to [`MAX_PERSISTENCE`]
+    pub fn with_persistence(mut self, decay: f32) -> DisplayFilter {
+        self.persistence = decay.clamp(0.0, MAX_PERSISTENCE);
+        self
+    }
+
+    /// Averages every frame with the one before
+    pub fn with_blending(mut self) -> DisplayFilter {
+        self.blend = true;
+        self
+    }
+
+    /// Only shows the display once the program finished drawing it
+    pub fn with_vblank(mut self) -> DisplayFilter {
+        self.vblank = true;
+        self
+    }
+
+    /// The frame to present
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    /// Notes how the next instruction changes the display, when drawing only on vblank
+    pub fn before_step(&mut self, emulator: &Emulator) {
+        if !self.vblank {
+            return;
+        }
+        self.check_drawn(emulator);
+        let opcode = emulator.opcode_at(emulator.pc());
+        match opcode & 0xF000 {
+            0xD000 => self.drawn_at = Some(emulator.pc()),
+            // Scrolling moves what was drawn, while clearing and switching resolution replace what is shown
+            _ if matches!(opcode, 0x00FB | 0x00FC) || opcode & 0xFFE0 == 0x00C0 => self.changed = true,
+            _ if matches!(opcode, 0x00E0 | 0x00FE | 0x00FF) => {
+                self.changed = true;
+                self.erased = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// Filters the frame that just ended
+    pub fn end_frame(&mut self, emulator: &Emulator) {
+        let current = (emulator.display, emulator.display_width(), emulator.display_height());
+        if self.vblank {
+            self.check_drawn(emulator);
+            if !self.changed || !self.erased || self.finished.is_none() {
+                self.finished = Some(current);
+            }
+            self.changed = false;
+        }
+        let (display, width, height) = match &self.finished {
+            Some(finished) if self.vblank => finished,
+            _ => &current,
+        };
+        let source = self.colors(display, *width, *height);
+        if source.len() != self.output.len() {
+            self.previous = None;
+            self.output.clone_from(&source);
+        }
+        let blended: Vec<[f32; 3]> = match (&self.previous, self.blend) {
+            (Some(previous), true) => source.iter().zip(previous).map(|(color, previous)| {
+                [0, 1, 2].map(|channel| (color[channel] + previous[channel]) / 2.0)
+            }).collect(),
+            _ => source.clone(),
+        };
+        // Unset pixels fade from what was shown before towards their colour
+        let background = self.palette.color(0).map(|channel| channel as f32);
+        for (output, color) in self.output.iter_mut().zip(&blended) {
+            *output = match *color == background {
+                true => [0, 1, 2].map(|channel| color[channel] + (output[channel] - color[channel]) * self.persistence),
+                false => *color,
+            };
+        }
+        self.previous = Some(source);
+        self.update_frame(emulator);
+    }
+
+    /// Shows the display as it is, without filtering. Used when the state changes outside of frames, such as when it is
+    /// loaded or changed in the debugger, where there is nothing to fade or blend from
+    pub fn reset(&mut self, emulator: &Emulator) {
+        self.finished = None;
+        self.drawn_at = None;
+        self.changed = false;
+        self.erased = false;
+        self.previous = None;
+        self.output = self.colors(&emulator.display, emulator.display_width(), emulator.display_height());
+        self.update_frame(emulator);
+    }
+
+    fn update_frame(&mut self, emulator: &Emulator) {
+        let (width, height) = (emulator.display_width(), emulator.display_height());
+        // The resolution can only change along with the display, so an output of another size is from before
+        if self.output.len() != width * height {
+            self.reset(emulator);
+            return;
+        }
+        self.frame.width = width;
+        self.frame.height = height;
+        self.frame.pixels = self.output.iter().map(|color| color.map(|channel| channel.round() as u8)).collect();
+    }
+
+    fn colors(&self, display: &[[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT], width: usize, height: usize) -> Vec<[f32; 3]> {
+        display.iter().take(height)
+            .flat_map(|row| row.iter().take(width).map(|pixel| self.palette.color(*pixel).map(|channel| channel as f32)))
+            .collect()
+    }
+
+    // Once a sprite was drawn, VF tells whether it erased pixels. The program counter stays on the instruction while it
+    // waits for the vertical blank to draw
+    fn check_drawn(&mut self, emulator: &Emulator) {
+        if self.drawn_at.is_some_and(|address| address != emulator.pc()) {
+            self.drawn_at = None;
+            self.changed = true;
+            self.erased = emulator.registers()[0xF] != 0;
+        }
+    }
+}
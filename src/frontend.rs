@@ -0,0 +1,61 @@
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::audio::Sound;
+use crate::display::Frame;
+use crate::emulator::Emulator;
+use crate::memory_view::MemoryRow;
+
+
+/// Input reported by a [`Frontend`]. Keys are named as by SDL, such as `Q`, `Up`, `Space` or `Escape`, which is also
+/// how key maps name them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrontendEvent {
+    Quit,
+    /// A key was pressed, or is repeating while held down
+    KeyDown { key: String, repeat: bool },
+    KeyUp { key: String },
+    /// The display has to be presented again, such as after the window was resized
+    Redraw,
+}
+
+/// The host an [`Interface`](crate::interface::Interface) runs the emulator on, which shows the display, plays the
+/// sound, reports input and keeps time. Everything else, from pacing frames to save states, is done by the interface,
+/// so every frontend behaves the same.
+pub trait Frontend {
+    /// Shows the frame, as filtered from the display of the emulator, which is also given for anything else the frontend
+    /// shows. Called whenever frames ran or the state changed otherwise
+    fn present(&mut self, emulator: &Emulator, frame: &Frame);
+
+    /// Plays the given sound until called with another one
+    fn play(&mut self, sound: Sound);
+
+    /// The input since the last poll
+    fn poll_events(&mut self) -> Vec<FrontendEvent>;
+
+    /// Whether the frontend has a key with the given name, to warn about key maps binding keys that can't be pressed
+    fn is_key(&self, _name: &str) -> bool {
+        true
+    }
+
+    /// Shows the rows of the memory view along with the frames presented from now on, or hides it. Frontends that can't
+    /// show it ignore it
+    fn show_memory(&mut self, _rows: Option<&[MemoryRow]>) {}
+
+    /// Switches between a window and the full screen, for frontends that have both
+    fn toggle_fullscreen(&mut self) {}
+
+    /// Tells the user about something that happened, such as a state being saved
+    fn message(&mut self, message: &str) {
+        println!("{}", message);
+    }
+
+    /// The current time, which frames are paced by
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    /// Waits until the next frame may be due
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use crate::audio::{AudioConfig, AudioSink, SAMPLE_RATE, ToneGenerator};
+use crate::debugger::{Debugger, StopReason};
+use crate::emulator::Emulator;
+use crate::movie::Movie;
+use crate::trace::Tracer;
+use crate::video::VideoSink;
+
+
+/// Why a headless run stopped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HaltReason {
+    /// The requested number of frames was run
+    FrameLimit,
+    /// The program reached a jump to itself, which never finishes
+    SelfJump(u16),
+    /// The program exited with the SUPER-CHIP `00FD` instruction
+    Exit,
+    /// The debugger stopped on a breakpoint, watchpoint, condition or emulator error
+    Stopped(StopReason),
+    /// The audio could not be written to the sink
+    AudioError(String),
+    /// The video could not be written to the sink
+    VideoError(String),
+    /// The trace could not be written to the sink
+    TraceError(String),
+}
+
+/// Runs the emulator without a window at a fixed number of instructions per frame, or as many as fit in a frame under
+/// the COSMAC VIP timing model, so every run of the same rom with the same input is identical. The timers tick once per
+/// frame, as they would at 60Hz.
+///
+/// Breakpoints, watchpoints and conditions are set through the debugger and stop the run when hit.
+pub struct HeadlessRunner {
+    emulator: Emulator,
+    debugger: Debugger,
+    instructions_per_frame: usize,
+    frame: u64,
+    // Instructions already run in the current frame, when a run stops in the middle of one
+    frame_instructions: usize,
+    audio: Option<(ToneGenerator, Box<dyn AudioSink>)>,
+    video: Option<Box<dyn VideoSink>>,
+    tracer: Option<Tracer>,
+    movie: Option<Movie>,
+}
+
+impl HeadlessRunner {
+    pub fn new(emulator: Emulator, instructions_per_frame: usize) -> HeadlessRunner {
+        HeadlessRunner {
+            emulator,
+            debugger: Debugger::new(),
+            instructions_per_frame,
+            frame: 0,
+            frame_instructions: 0,
+            audio: None,
+            video: None,
+            tracer: None,
+            movie: None,
+        }
+    }
+
+    /// Replays the key changes of a movie. The emulator should be built with [`Movie::emulator`] and the runner
+    /// should run the movie's number of instructions per frame, for the replay to match the recording
+    pub fn with_movie(mut self, movie: Movie) -> HeadlessRunner {
+        self.movie = Some(movie);
+        self
+    }
+
+    pub fn movie(&self) -> Option<&Movie> {
+        self.movie.as_ref()
+    }
+
+    /// Writes the tone played by the sound timer to the given sink, one frame of samples at a time
+    pub fn with_audio(mut self, config: AudioConfig, sink: Box<dyn AudioSink>) -> HeadlessRunner {
+        self.audio = Some((ToneGenerator::new(config, SAMPLE_RATE), sink));
+        self
+    }
+
+    /// Writes the display to the given sink after every frame
+    pub fn with_video(mut self, sink: Box<dyn VideoSink>) -> HeadlessRunner {
+        self.video = Some(sink);
+        self
+    }
+
+    /// Traces every instruction that runs
+    pub fn with_tracer(mut self, tracer: Tracer) -> HeadlessRunner {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    pub fn emulator_mut(&mut self) -> &mut Emulator {
+        &mut self.emulator
+    }
+
+    pub fn into_emulator(self) -> Emulator {
+        self.emulator
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// The number of frames run so far
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Runs the instructions of a single frame and ticks the timers, unless the program halts first
+    pub fn run_frame(&mut self) -> Option<HaltReason> {
+        // Resume from wherever the last run stopped
+        if self.debugger.is_paused() {
+            self.debugger.resume();
+        }
+        if self.frame_instructions == 0 {
+            if let Some(movie) = &self.movie {
+                for event in movie.events_at(self.frame) {
+                    self.emulator.keypad[event.key as usize] = event.pressed;
+                }
+            }
+        }
+        loop {
+            if self.emulator.halted() {
+                return Some(HaltReason::Exit);
+            }
+            if self.emulator.frame_complete(self.frame_instructions, self.instructions_per_frame) {
+                break;
+            }
+            let pc = self.emulator.pc();
+            if pc <= 0x0FFF && self.emulator.opcode_at(pc) == 0x1000 | pc {
+                return Some(HaltReason::SelfJump(pc));
+            }
+            if let Some(tracer) = &mut self.tracer {
+                tracer.before_step(&self.emulator);
+            }
+            self.debugger.step(&mut self.emulator);
+            self.frame_instructions += 1;
+            if let Some(Err(e)) = self.tracer.as_mut().map(|tracer| tracer.after_step(&self.emulator)) {
+                return Some(HaltReason::TraceError(e.to_string()));
+            }
+            if let Some(reason) = self.debugger.take_stop_reason() {
+                return Some(HaltReason::Stopped(reason));
+            }
+        }
+        if let Some((generator, sink)) = &mut self.audio {
+            let mut samples = [0.0; (SAMPLE_RATE / 60) as usize];
+            generator.fill(&mut samples, self.emulator.sound());
+            if let Err(e) = sink.write_samples(&samples) {
+                return Some(HaltReason::AudioError(e.to_string()));
+            }
+        }
+        if let Some(sink) = &mut self.video {
+            if let Err(e) = sink.write_frame(&self.emulator) {
+                return Some(HaltReason::VideoError(e.to_string()));
+            }
+        }
+        self.emulator.tick_clock();
+        self.frame += 1;
+        self.frame_instructions = 0;
+        None
+    }
+
+    /// Completes the audio output, if any. Must be called once the run is over
+    pub fn finish_audio(&mut self) -> io::Result<()> {
+        match &mut self.audio {
+            Some((_, sink)) => sink.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Completes the video output, if any. Must be called once the run is over
+    pub fn finish_video(&mut self) -> io::Result<()> {
+        match &mut self.video {
+            Some(sink) => sink.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Completes the trace, if any. Must be called once the run is over
+    pub fn finish_trace(&mut self) -> io::Result<()> {
+        match &mut self.tracer {
+            Some(tracer) => tracer.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs up to the given number of frames, returning why the run stopped
+    pub fn run(&mut self, frames: u64) -> HaltReason {
+        for _ in 0..frames {
+            if let Some(reason) = self.run_frame() {
+                return reason;
+            }
+        }
+        HaltReason::FrameLimit
+    }
+}
+
+impl Display for HaltReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HaltReason::FrameLimit => write!(f, "Reached the frame limit"),
+            HaltReason::SelfJump(address) => write!(f, "Jump to itself at {:#05x}", address),
+            HaltReason::Exit => write!(f, "Program exited"),
+            HaltReason::Stopped(reason) => write!(f, "{}", reason),
+            HaltReason::AudioError(e) => write!(f, "Error writing audio: {}", e),
+            HaltReason::VideoError(e) => write!(f, "Error writing video: {}", e),
+            HaltReason::TraceError(e) => write!(f, "Error writing trace: {}", e),
+        }
+    }
+}
@@ -1,202 +1,663 @@
-use std::sync::{Arc, mpsc, RwLock};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::sync::mpsc;
 use std::thread;
-use std::time::Duration;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::render::WindowCanvas;
+use crate::audio::Sound;
+use crate::debugger::{self, Debugger};
+use crate::display::Palette;
 use crate::emulator::Emulator;
+use crate::filter::DisplayFilter;
+use crate::frontend::{Frontend, FrontendEvent};
+use crate::keymap::Keymap;
+use crate::memory_view::{BYTES_PER_ROW, MemoryView, VIEW_ROWS};
+use crate::movie::Movie;
+use crate::rewind::Rewind;
+use crate::scheduler::Scheduler;
+use crate::trace::Tracer;
+use crate::video::VideoSink;
 
+const SAVE_STATE_SLOTS: usize = 10;
 
+// Keys used by the interface itself, which can't press keypad keys
+const RESERVED_KEYS: [&str; 14] = [
+    "Escape", "F2", "F5", "F6", "F7", "F9", "F11", "F12", "Backspace", "P", "N", "Tab", "-", "=",
+];
+
+// A movie being recorded to a file or played back
+struct MovieSession {
+    movie: Movie,
+    record_path: Option<PathBuf>,
+    frame: u64,
+}
+
+/// Runs an emulator on a [`Frontend`], such as a window or the terminal. Everything happens on one thread, one emulated
+/// frame at a time: the [`Scheduler`] decides how many frames are due, and each runs its instructions and ticks the
+/// timers once.
 pub struct Interface {
     running: bool,
-    emulator: Arc<RwLock<Emulator>>,
-    delay: u64,
+    redraw: bool,
+    emulator: Emulator,
+    instructions_per_frame: usize,
+    // Instructions already run in the current frame, when the debugger paused in the middle of one
+    frame_instructions: usize,
+    scheduler: Scheduler,
+    state_path: Option<PathBuf>,
+    state_slot: usize,
+    state_slots: [Option<Vec<u8>>; SAVE_STATE_SLOTS],
+    rewind: Option<Rewind>,
+    rewinding: bool,
+    debugger: Option<Debugger>,
+    movie: Option<MovieSession>,
+    keymap: Keymap,
+    // The bound host keys currently held down
+    held_keys: HashSet<String>,
+    filter: DisplayFilter,
+    screenshot_palette: Palette,
+    screenshot_scale: usize,
+    video: Option<Box<dyn VideoSink>>,
+    memory_view: Option<MemoryView>,
+    tracer: Option<Tracer>,
 }
 
 impl Interface {
-    pub fn new(emulator: Emulator, delay: u64) -> Interface {
+    /// Runs the given number of instructions per frame, unless the emulator uses the COSMAC VIP timing model
+    pub fn new(emulator: Emulator, instructions_per_frame: usize) -> Interface {
         Interface {
             running: true,
-            emulator: Arc::new(RwLock::new(emulator)),
-            delay,
+            redraw: true,
+            emulator,
+            instructions_per_frame,
+            frame_instructions: 0,
+            scheduler: Scheduler::new(),
+            state_path: None,
+            state_slot: 0,
+            state_slots: Default::default(),
+            rewind: None,
+            rewinding: false,
+            debugger: None,
+            movie: None,
+            keymap: Keymap::default(),
+            held_keys: HashSet::new(),
+            filter: DisplayFilter::new(Palette::default()),
+            screenshot_palette: Palette::default(),
+            screenshot_scale: 10,
+            video: None,
+            memory_view: None,
+            tracer: None,
         }
     }
 
-    pub fn run(mut self) {
+    /// Stores quick save slots on disk next to the given path, as `<path>.state0` through `<path>.state9`,
+    /// instead of keeping them in memory. Screenshots are saved next to it too, instead of in the working directory
+    pub fn with_state_path(mut self, path: PathBuf) -> Interface {
+        self.state_path = Some(path);
+        self
+    }
 
-        let (display_tx, display_rx) = mpsc::channel();
-        let (clock_tx, clock_rx) = mpsc::channel();
-        let (key_tx, key_rx) = mpsc::channel();
-        let (run_tx, run_rx) = mpsc::channel();
+    /// Captures a snapshot every frame into the given history, which is stepped back through while backspace is held
+    pub fn with_rewind(mut self, rewind: Rewind) -> Interface {
+        self.rewind = Some(rewind);
+        self
+    }
 
-        let emulator = self.emulator.clone();
-        let handle = thread::spawn(move || {
-            while run_rx.try_recv().is_err() {
-                if emulator.write().unwrap().step().unwrap() {
-                    display_tx.send(()).unwrap();
-                }
-                if clock_rx.try_recv().is_ok() {
-                    emulator.write().unwrap().tick_clock();
+    /// Presses keypad keys with the host keys of the given key map instead of the default COSMAC VIP layout. Host
+    /// keys that the frontend doesn't have are ignored with a warning, as are bindings of the keys used by the
+    /// interface itself
+    pub fn with_keymap(mut self, keymap: Keymap) -> Interface {
+        self.keymap = keymap;
+        self
+    }
+
+    /// Paces frames with the given scheduler, to start with a different speed or paused
+    pub fn with_scheduler(mut self, scheduler: Scheduler) -> Interface {
+        self.scheduler = scheduler;
+        self
+    }
+
+    /// Presents the display through the given filter, which also sets its palette, instead of as it is in white on
+    /// black
+    pub fn with_filter(mut self, filter: DisplayFilter) -> Interface {
+        self.filter = filter;
+        self
+    }
+
+    /// Takes the screenshots saved with F12 in the given palette and at the given scale, see
+    /// [`capture_size`](crate::display::capture_size). They are numbered `<path>.screenshot0.png` and onwards, skipping
+    /// files that already exist
+    pub fn with_screenshots(mut self, palette: Palette, scale: usize) -> Interface {
+        self.screenshot_palette = palette;
+        self.screenshot_scale = scale;
+        self
+    }
+
+    /// Writes the display to the given sink after every frame that runs or rewinds, until the interface closes
+    pub fn with_video(mut self, sink: Box<dyn VideoSink>) -> Interface {
+        self.video = Some(sink);
+        self
+    }
+
+    /// Starts with the memory view open, which F2 opens and closes
+    pub fn with_memory_view(mut self) -> Interface {
+        self.memory_view = Some(MemoryView::new());
+        self
+    }
+
+    /// Traces the instructions that run, until the interface closes
+    pub fn with_tracer(mut self, tracer: Tracer) -> Interface {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Runs the emulator under the given debugger, controlled through a command prompt on the terminal
+    pub fn with_debugger(mut self, debugger: Debugger) -> Interface {
+        self.debugger = Some(debugger);
+        self
+    }
+
+    /// Records every keypad change into the given movie, which is written to the path when the interface closes.
+    /// While a movie is recorded or played back, rewinding, loading states and the debugger are disabled
+    pub fn with_movie_recording(mut self, movie: Movie, path: PathBuf) -> Interface {
+        self.movie = Some(MovieSession { movie, record_path: Some(path), frame: 0 });
+        self
+    }
+
+    /// Plays back the key changes of a movie in place of the keyboard. The emulator should be built with
+    /// [`Movie::emulator`] and the interface should run the movie's number of instructions per frame. Once the movie
+    /// ends, whether the display matches the recording is printed
+    pub fn with_movie_playback(mut self, movie: Movie) -> Interface {
+        self.movie = Some(MovieSession { movie, record_path: None, frame: 0 });
+        self
+    }
+
+    /// Runs until the frontend quits. The frontend is closed before the result of a recorded movie is printed
+    pub fn run<F: Frontend>(mut self, mut frontend: F) {
+        if self.movie.is_some() {
+            self.rewind = None;
+            self.debugger = None;
+        }
+        let console = self.debugger.is_some().then(Self::spawn_debugger_console);
+        self.check_keymap(&mut frontend);
+        self.filter.reset(&self.emulator);
+        if self.memory_view.is_some() {
+            self.emulator.set_record_memory_accesses(true);
+        }
+
+        while self.running {
+            for event in frontend.poll_events() {
+                self.handle_event(&mut frontend, &event);
+                // Events can change what the frontend shows even while paused
+                self.redraw = true;
+            }
+            if let Some(console) = &console {
+                while let Ok(line) = console.try_recv() {
+                    self.execute_debugger_command(&line);
                 }
-                while let Ok((key, state)) = key_rx.try_recv() {
-                    emulator.write().unwrap().keypad[key as usize] = state;
+            }
+
+            let frames = self.scheduler.frames_due(frontend.now());
+            for _ in 0..frames {
+                if self.rewinding {
+                    self.rewind_frame(&mut frontend);
+                } else if !self.run_frame(&mut frontend) {
+                    // The rest of the frames due are dropped once execution stops, such as after an error
+                    break;
                 }
-                thread::sleep(Duration::from_micros(self.delay));
             }
-        });
 
-        let sdl = sdl2::init().unwrap();
-        let video_subsystem = sdl.video().unwrap();
-        let window = video_subsystem.window("RustyChip", 640, 320)
-            .position_centered()
-            .build()
-            .unwrap();
+            if frames > 0 || self.redraw {
+                self.redraw = false;
+                let rows = self.memory_view.as_ref().map(|view| view.rows(&self.emulator, self.can_edit_memory()));
+                frontend.show_memory(rows.as_deref());
+                frontend.present(&self.emulator, self.filter.frame());
+            }
+            frontend.play(self.sound());
+
+            frontend.sleep(self.scheduler.time_to_next_frame(frontend.now()));
+        }
+        drop(frontend);
+        self.finish_movie();
+        if let Some(Err(e)) = self.video.as_mut().map(|sink| sink.finish()) {
+            println!("Error writing video: {}", e);
+        }
+        if let Some(Err(e)) = self.tracer.as_mut().map(|tracer| tracer.finish()) {
+            println!("Error writing trace: {}", e);
+        }
+    }
 
-        let mut canvas = window.into_canvas().build().unwrap();
+    // Runs the rest of the current frame, then ticks the timers. A frame interrupted by the debugger or an error is
+    // finished once it resumes. Returns whether the frame completed
+    fn run_frame(&mut self, frontend: &mut dyn Frontend) -> bool {
+        if self.frame_instructions == 0 {
+            if let Some(session) = self.movie.as_ref().filter(|session| session.record_path.is_none()) {
+                for event in session.movie.events_at(session.frame) {
+                    self.emulator.keypad[event.key as usize] = event.pressed;
+                }
+            }
+        }
+        while !self.emulator.frame_complete(self.frame_instructions, self.instructions_per_frame) {
+            self.filter.before_step(&self.emulator);
+            let steps = self.emulator.steps();
+            let running = self.step(frontend);
+            // Steps the debugger holds back don't run an instruction, so they don't count towards the frame
+            if self.emulator.steps() != steps {
+                self.frame_instructions += 1;
+            }
+            if !running {
+                return false;
+            }
+        }
+        self.filter.end_frame(&self.emulator);
+        self.record_video(frontend);
+        if let Some(view) = &mut self.memory_view {
+            view.end_frame();
+        }
+        self.emulator.tick_clock();
+        self.frame_instructions = 0;
+        if let Some(rewind) = &mut self.rewind {
+            rewind.capture(self.emulator.save_state());
+        }
+        if let Some(session) = &mut self.movie {
+            session.frame += 1;
+            if session.record_path.is_none() && session.frame == session.movie.frames {
+                let result = if session.movie.verify(&self.emulator) { "matches" } else { "does not match" };
+                frontend.message(&format!("Movie finished after {} frames, the display {} the recording", session.frame, result));
+            }
+        }
+        true
+    }
 
-        let mut event_pump = sdl.event_pump().unwrap();
+    // Runs a single instruction, through the debugger if there is one. Returns whether execution can go on
+    fn step(&mut self, frontend: &mut dyn Frontend) -> bool {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.before_step(&self.emulator);
+        }
+        let running = self.step_emulator(frontend);
+        if let Some(view) = &mut self.memory_view {
+            view.record_writes(&self.emulator);
+        }
+        // Tracing stops if the trace can't be written
+        if let Some(Err(e)) = self.tracer.as_mut().map(|tracer| tracer.after_step(&self.emulator)) {
+            frontend.message(&format!("Error writing trace, tracing stopped: {}", e));
+            self.tracer = None;
+        }
+        running
+    }
 
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.clear();
-        canvas.present();
-        while self.running {
-            for event in event_pump.poll_iter() {
-                self.handle_event(&event, &key_tx);
+    fn step_emulator(&mut self, frontend: &mut dyn Frontend) -> bool {
+        match &mut self.debugger {
+            Some(debugger) => {
+                debugger.step(&mut self.emulator);
+                if let Some(reason) = debugger.take_stop_reason() {
+                    print!("{}\n{}\n> ", reason, debugger::format_registers(&self.emulator));
+                    io::stdout().flush().unwrap();
+                    // Show where the debugger stopped, even in the middle of a frame
+                    self.filter.reset(&self.emulator);
+                }
+                !debugger.is_paused()
             }
+            None => match self.emulator.step() {
+                Ok(_) => true,
+                Err(e) => {
+                    frontend.message(&format!("Emulator error, pausing: {}", e));
+                    self.scheduler.set_paused(true);
+                    false
+                }
+            },
+        }
+    }
+
+    // Steps back one snapshot of the rewind history in place of running a frame
+    fn rewind_frame(&mut self, frontend: &mut dyn Frontend) {
+        if let Some(state) = self.rewind.as_mut().and_then(|rewind| rewind.step_back()) {
+            self.emulator.load_state(&state).unwrap();
+            self.frame_instructions = 0;
+            self.filter.end_frame(&self.emulator);
+            self.record_video(frontend);
+        }
+    }
+
+    // Recording stops if the video can't be written
+    fn record_video(&mut self, frontend: &mut dyn Frontend) {
+        if let Some(Err(e)) = self.video.as_mut().map(|sink| sink.write_frame(&self.emulator)) {
+            frontend.message(&format!("Error writing video, recording stopped: {}", e));
+            self.video = None;
+        }
+    }
 
-            if display_rx.try_recv().is_ok() {
-                self.draw(&mut canvas);
+    fn finish_movie(&mut self) {
+        let session = match &mut self.movie {
+            Some(session) => session,
+            None => return,
+        };
+        if let Some(path) = &session.record_path {
+            session.movie.finish(session.frame, &self.emulator);
+            match session.movie.save(path) {
+                Ok(()) => println!("Recorded {} frames to {}", session.frame, path.display()),
+                Err(e) => println!("Error saving movie: {}", e),
             }
-            // If multiple instructions trigger a redraw, we redraw only once and consume the redraw requests
-            while display_rx.try_recv().is_ok() {
+        }
+    }
+
+    // Reads debugger commands from the terminal on a separate thread, as reading blocks. The thread is left blocked on
+    // standard input when the interface closes
+    fn spawn_debugger_console() -> mpsc::Receiver<String> {
+        let (line_tx, line_rx) = mpsc::channel();
+        thread::spawn(move || {
+            println!("Debugger started paused, type 'help' for a list of commands");
+            print!("> ");
+            io::stdout().flush().unwrap();
+            for line in io::stdin().lock().lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                if line_tx.send(line).is_err() {
+                    break;
+                }
             }
+        });
+        line_rx
+    }
 
-            clock_tx.send(()).unwrap();
-            thread::sleep(Duration::from_nanos(1_000_000_000u64 / 60));
+    fn execute_debugger_command(&mut self, line: &str) {
+        let debugger = match &mut self.debugger {
+            Some(debugger) => debugger,
+            None => return,
+        };
+        match debugger.execute(&mut self.emulator, line) {
+            Ok(output) if !output.is_empty() => println!("{}", output),
+            Ok(_) => {}
+            Err(e) => println!("Error: {}", e),
+        }
+        if debugger.is_paused() {
+            print!("> ");
+            io::stdout().flush().unwrap();
         }
-        run_tx.send(()).unwrap();
-        handle.join().unwrap();
+        // Commands can change registers and memory
+        self.filter.reset(&self.emulator);
+        self.redraw = true;
     }
 
-    fn handle_event(&mut self, event: &Event, key_tx: &mpsc::Sender<(u8, bool)>) {
+    fn handle_event(&mut self, frontend: &mut dyn Frontend, event: &FrontendEvent) {
         match event {
-            Event::Quit {..} |
-            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                self.running = false;
-            },
-            Event::KeyDown { keycode: Some(Keycode::Num1), .. } => {
-                key_tx.send((0, true)).unwrap();
-            },
-            Event::KeyDown { keycode: Some(Keycode::Num2), .. } => {
-                key_tx.send((1, true)).unwrap();
-            },
-            Event::KeyDown { keycode: Some(Keycode::Num3), .. } => {
-                key_tx.send((2, true)).unwrap();
-            },
-            Event::KeyDown { keycode: Some(Keycode::Num4), .. } => {
-                key_tx.send((3, true)).unwrap();
-            },
-            Event::KeyDown { keycode: Some(Keycode::Q), .. } => {
-                key_tx.send((4, true)).unwrap();
-            },
-            Event::KeyDown { keycode: Some(Keycode::W), .. } => {
-                key_tx.send((5, true)).unwrap();
-            },
-            Event::KeyDown { keycode: Some(Keycode::E), .. } => {
-                key_tx.send((6, true)).unwrap();
-            },
-            Event::KeyDown { keycode: Some(Keycode::R), .. } => {
-                key_tx.send((7, true)).unwrap();
-            },
-            Event::KeyDown { keycode: Some(Keycode::A), .. } => {
-                key_tx.send((8, true)).unwrap();
-            },
-            Event::KeyDown { keycode: Some(Keycode::S), .. } => {
-                key_tx.send((9, true)).unwrap();
+            FrontendEvent::Quit => self.running = false,
+            FrontendEvent::Redraw => self.redraw = true,
+            FrontendEvent::KeyDown { key, repeat } => match key.as_str() {
+                "Escape" => self.running = false,
+                "F5" => self.quick_save(frontend),
+                "F9" => self.quick_load(frontend),
+                "F11" if !repeat => frontend.toggle_fullscreen(),
+                "F12" if !repeat => self.screenshot(frontend),
+                "F6" => {
+                    self.state_slot = (self.state_slot + SAVE_STATE_SLOTS - 1) % SAVE_STATE_SLOTS;
+                    frontend.message(&format!("Selected save state slot {}", self.state_slot));
+                },
+                "F7" => {
+                    self.state_slot = (self.state_slot + 1) % SAVE_STATE_SLOTS;
+                    frontend.message(&format!("Selected save state slot {}", self.state_slot));
+                },
+                "Backspace" if !repeat => self.rewinding = self.rewind.is_some(),
+                "P" if !repeat => {
+                    let paused = !self.scheduler.is_paused();
+                    self.scheduler.set_paused(paused);
+                    frontend.message(if paused { "Paused" } else { "Resumed" });
+                },
+                "N" => self.scheduler.advance_frame(),
+                "F2" if !repeat => self.toggle_memory_view(),
+                "Tab" if !repeat => self.scheduler.set_fast_forwarding(true),
+                "-" => {
+                    self.scheduler.set_speed(self.scheduler.speed() / 2.0);
+                    frontend.message(&format!("Speed {}x", self.scheduler.speed()));
+                },
+                "=" => {
+                    self.scheduler.set_speed(self.scheduler.speed() * 2.0);
+                    frontend.message(&format!("Speed {}x", self.scheduler.speed()));
+                },
+                key if self.memory_view.is_some() && self.edit_memory(key) => {}
+                key if !repeat => self.update_key(key, true),
+                _ => {}
+            },
+            FrontendEvent::KeyUp { key } => match key.as_str() {
+                "Backspace" => self.rewinding = false,
+                "Tab" => self.scheduler.set_fast_forwarding(false),
+                key => self.update_key(key, false),
             },
-            Event::KeyDown { keycode: Some(Keycode::D), .. } => {
-                key_tx.send((10, true)).unwrap();
-            },
-            Event::KeyDown { keycode: Some(Keycode::F), .. } => {
-                key_tx.send((11, true)).unwrap();
-            },
-            Event::KeyDown { keycode: Some(Keycode::Z), .. } => {
-                key_tx.send((12, true)).unwrap();
-            },
-            Event::KeyDown { keycode: Some(Keycode::X), .. } => {
-                key_tx.send((13, true)).unwrap();
-            },
-            Event::KeyDown { keycode: Some(Keycode::C), .. } => {
-                key_tx.send((14, true)).unwrap();
-            },
-            Event::KeyDown { keycode: Some(Keycode::V), .. } => {
-                key_tx.send((15, true)).unwrap();
-            },
-            Event::KeyUp { keycode: Some(Keycode::Num1), .. } => {
-                key_tx.send((0, false)).unwrap();
-            },
-            Event::KeyUp { keycode: Some(Keycode::Num2), .. } => {
-                key_tx.send((1, false)).unwrap();
-            },
-            Event::KeyUp { keycode: Some(Keycode::Num3), .. } => {
-                key_tx.send((2, false)).unwrap();
-            },
-            Event::KeyUp { keycode: Some(Keycode::Num4), .. } => {
-                key_tx.send((3, false)).unwrap();
-            },
-            Event::KeyUp { keycode: Some(Keycode::Q), .. } => {
-                key_tx.send((4, false)).unwrap();
-            },
-            Event::KeyUp { keycode: Some(Keycode::W), .. } => {
-                key_tx.send((5, false)).unwrap();
-            },
-            Event::KeyUp { keycode: Some(Keycode::E), .. } => {
-                key_tx.send((6, false)).unwrap();
-            },
-            Event::KeyUp { keycode: Some(Keycode::R), .. } => {
-                key_tx.send((7, false)).unwrap();
-            },
-            Event::KeyUp { keycode: Some(Keycode::A), .. } => {
-                key_tx.send((8, false)).unwrap();
-            },
-            Event::KeyUp { keycode: Some(Keycode::S), .. } => {
-                key_tx.send((9, false)).unwrap();
-            },
-            Event::KeyUp { keycode: Some(Keycode::D), .. } => {
-                key_tx.send((10, false)).unwrap();
-            },
-            Event::KeyUp { keycode: Some(Keycode::F), .. } => {
-                key_tx.send((11, false)).unwrap();
-            },
-            Event::KeyUp { keycode: Some(Keycode::Z), .. } => {
-                key_tx.send((12, false)).unwrap();
-            },
-            Event::KeyUp { keycode: Some(Keycode::X), .. } => {
-                key_tx.send((13, false)).unwrap();
-            },
-            Event::KeyUp { keycode: Some(Keycode::C), .. } => {
-                key_tx.send((14, false)).unwrap();
-            },
-            Event::KeyUp { keycode: Some(Keycode::V), .. } => {
-                key_tx.send((15, false)).unwrap();
+        }
+    }
+
+    // Warns about host keys in the key map that can't press keypad keys
+    fn check_keymap(&self, frontend: &mut dyn Frontend) {
+        for key in 0..16 {
+            for name in self.keymap.host_keys(key) {
+                if RESERVED_KEYS.iter().any(|reserved| reserved.eq_ignore_ascii_case(name)) {
+                    frontend.message(&format!("Warning: `{}` is used by the interface and can't be bound in the key map", name));
+                } else if !frontend.is_key(name) {
+                    frontend.message(&format!("Warning: unknown key `{}` in the key map", name));
+                }
+            }
+        }
+    }
+
+    // A keypad key is held while any of its host keys is, so it only changes when the first is pressed or the last
+    // released. While a movie plays back the keypad only follows the movie
+    fn update_key(&mut self, host_key: &str, pressed: bool) {
+        let key = match self.keymap.key_for(host_key) {
+            Some(key) => key,
+            None => return,
+        };
+        if self.movie.as_ref().is_some_and(|session| session.record_path.is_none()) {
+            return;
+        }
+        let was_held = self.is_held(key);
+        if pressed {
+            self.held_keys.insert(host_key.to_string());
+        } else {
+            self.held_keys.remove(host_key);
+        }
+        if self.is_held(key) != was_held {
+            self.emulator.keypad[key as usize] = pressed;
+            // Changes take effect from the next frame, which is where the movie replays them
+            if let Some(session) = &mut self.movie {
+                session.movie.record(session.frame, key, pressed);
+            }
+        }
+    }
+
+    fn is_held(&self, key: u8) -> bool {
+        self.held_keys.iter().any(|held| self.keymap.key_for(held) == Some(key))
+    }
+
+    // The memory view records the writes of the program, which the emulator only reports while it is open. The
+    // debugger turns recording back on for its watchpoints
+    fn toggle_memory_view(&mut self) {
+        self.memory_view = match self.memory_view {
+            Some(_) => None,
+            None => Some(MemoryView::new()),
+        };
+        self.emulator.set_record_memory_accesses(self.memory_view.is_some());
+    }
+
+    // While the memory view is open, page up and down scroll it and home follows the program counter again. While
+    // paused, the arrows also move its cursor and hex digits overwrite memory in place of pressing keypad keys, unless
+    // a movie is recorded or played back, which changing memory would break. Returns whether the key was used
+    fn edit_memory(&mut self, key: &str) -> bool {
+        let editing = self.can_edit_memory();
+        let view = match &mut self.memory_view {
+            Some(view) => view,
+            None => return false,
+        };
+        match key {
+            "PageUp" => view.scroll(&self.emulator, -(VIEW_ROWS as isize)),
+            "PageDown" => view.scroll(&self.emulator, VIEW_ROWS as isize),
+            "Home" => view.follow(),
+            "Left" if editing => view.move_cursor(&self.emulator, -1),
+            "Right" if editing => view.move_cursor(&self.emulator, 1),
+            "Up" if editing => view.move_cursor(&self.emulator, -(BYTES_PER_ROW as isize)),
+            "Down" if editing => view.move_cursor(&self.emulator, BYTES_PER_ROW as isize),
+            key => match u8::from_str_radix(key, 16) {
+                Ok(digit) if editing && key.len() == 1 => view.type_digit(&mut self.emulator, digit),
+                _ => return false,
             },
-            _ => {}
         }
+        true
     }
 
-    fn draw(&mut self, canvas: &mut WindowCanvas) {
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.clear();
-        canvas.set_draw_color(Color::RGB(255, 255, 255));
-        let emulator = self.emulator.read().unwrap();
-        for x in 0..64 {
-            for y in 0..32 {
-                if emulator.display[y][x] {
-                    canvas.fill_rect(sdl2::rect::Rect::new(x as i32 * 10, y as i32 * 10, 10, 10)).unwrap();
+    fn can_edit_memory(&self) -> bool {
+        self.is_paused() && self.movie.is_none()
+    }
+
+    fn is_paused(&self) -> bool {
+        self.scheduler.is_paused() || self.debugger.as_ref().is_some_and(|debugger| debugger.is_paused())
+    }
+
+    // Sound plays while the sound timer runs, except while rewinding or paused
+    fn sound(&self) -> Sound {
+        if self.is_paused() || self.rewinding {
+            return Sound::Silent;
+        }
+        self.emulator.sound()
+    }
+
+    fn state_slot_path(&self) -> Option<PathBuf> {
+        self.state_path.as_ref().map(|path| {
+            let mut path = path.clone().into_os_string();
+            path.push(format!(".state{}", self.state_slot));
+            PathBuf::from(path)
+        })
+    }
+
+    #[cfg(feature = "capture")]
+    fn screenshot(&mut self, frontend: &mut dyn Frontend) {
+        let base = self.state_path.clone().unwrap_or_else(|| PathBuf::from("screenshot")).into_os_string();
+        let path = (0..).map(|number| {
+            let mut path = base.clone();
+            path.push(format!(".screenshot{}.png", number));
+            PathBuf::from(path)
+        }).find(|path| !path.exists()).unwrap();
+        let result = fs::File::create(&path).map_err(|e| e.to_string()).and_then(|file| {
+            crate::display::write_png(&self.emulator, io::BufWriter::new(file), &self.screenshot_palette, self.screenshot_scale)
+                .map_err(|e| e.to_string())
+        });
+        match result {
+            Ok(()) => frontend.message(&format!("Saved screenshot to {}", path.display())),
+            Err(e) => frontend.message(&format!("Error saving screenshot {}: {}", path.display(), e)),
+        }
+    }
+
+    #[cfg(not(feature = "capture"))]
+    fn screenshot(&mut self, frontend: &mut dyn Frontend) {
+        frontend.message("Screenshots need the capture feature");
+    }
+
+    fn quick_save(&mut self, frontend: &mut dyn Frontend) {
+        let state = self.emulator.save_state();
+        match self.state_slot_path() {
+            Some(path) => {
+                if let Err(e) = fs::write(&path, state) {
+                    frontend.message(&format!("Error writing save state {}: {}", path.display(), e));
+                    return;
+                }
+            }
+            None => self.state_slots[self.state_slot] = Some(state),
+        }
+        frontend.message(&format!("Saved state to slot {}", self.state_slot));
+    }
+
+    fn quick_load(&mut self, frontend: &mut dyn Frontend) {
+        if self.movie.is_some() {
+            frontend.message("States can't be loaded while a movie is recorded or played back");
+            return;
+        }
+        let state = match self.state_slot_path() {
+            Some(path) => match fs::read(&path) {
+                Ok(state) => state,
+                Err(e) => {
+                    frontend.message(&format!("Error reading save state {}: {}", path.display(), e));
+                    return;
                 }
+            },
+            None => match &self.state_slots[self.state_slot] {
+                Some(state) => state.clone(),
+                None => {
+                    frontend.message(&format!("Save state slot {} is empty", self.state_slot));
+                    return;
+                }
+            },
+        };
+        match self.emulator.load_state(&state) {
+            Ok(()) => {
+                self.frame_instructions = 0;
+                self.filter.reset(&self.emulator);
+                self.redraw = true;
+                frontend.message(&format!("Loaded state from slot {}", self.state_slot));
             }
+            Err(e) => frontend.message(&format!("Error loading save state: {}", e)),
         }
-        canvas.present();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+    use super::*;
+    use crate::display::Frame;
+
+    // What the test frontend was shown
+    #[derive(Default)]
+    struct Shown {
+        registers: Vec<[u8; 16]>,
+        messages: Vec<String>,
+    }
+
+    // A frontend that a second passes for between polls, so that frames are due in batches, and that quits after the
+    // given number of polls
+    struct TestFrontend {
+        start: Instant,
+        polls: u64,
+        quit_after: u64,
+        shown: Rc<RefCell<Shown>>,
+    }
+
+    impl Frontend for TestFrontend {
+        fn present(&mut self, emulator: &Emulator, _frame: &Frame) {
+            self.shown.borrow_mut().registers.push(*emulator.registers());
+        }
+
+        fn play(&mut self, _sound: Sound) {}
+
+        fn poll_events(&mut self) -> Vec<FrontendEvent> {
+            self.polls += 1;
+            if self.polls > self.quit_after { vec![FrontendEvent::Quit] } else { Vec::new() }
+        }
+
+        fn message(&mut self, message: &str) {
+            self.shown.borrow_mut().messages.push(message.to_string());
+        }
+
+        fn now(&self) -> Instant {
+            self.start + Duration::from_secs(self.polls)
+        }
+
+        fn sleep(&mut self, _duration: Duration) {}
+    }
+
+    #[test]
+    fn error_stops_the_frames_due() {
+        // Counts up in V0 one instruction per frame, with an invalid instruction in the second frame
+        let program = [0x70, 0x01, 0x80, 0x0F, 0x70, 0x01, 0x70, 0x01, 0x12, 0x00];
+        let emulator = Emulator::builder(&program).build().unwrap();
+        let shown = Rc::new(RefCell::new(Shown::default()));
+        let frontend = TestFrontend { start: Instant::now(), polls: 0, quit_after: 2, shown: shown.clone() };
+        Interface::new(emulator, 1).run(frontend);
+
+        // The second poll makes four frames due, of which only the first and the error run
+        let shown = shown.borrow();
+        assert_eq!(shown.messages.len(), 1);
+        assert!(shown.messages[0].starts_with("Emulator error, pausing"));
+        assert_eq!(shown.registers.last().unwrap()[0], 1);
     }
 }
\ No newline at end of file
@@ -0,0 +1,111 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use thiserror::Error;
+
+
+#[derive(Error, Debug)]
+pub enum KeymapError {
+    #[error("line {line}: {message}")]
+    Parse { line: usize, message: String },
+    #[error("{0}")]
+    Io(#[from] io::Error),
+}
+
+/// Which host keys press each of the 16 keys of the keypad. Host keys are named as by SDL, such as `Q`, `Up`,
+/// `Space` or `Keypad 8`, and compared without regard to case.
+///
+/// The default follows the layout of the COSMAC VIP keypad on the left of a QWERTY keyboard:
+/// ```text
+///     1 2 3 C      1 2 3 4
+///     4 5 6 D  ->  Q W E R
+///     7 8 9 E      A S D F
+///     A 0 B F      Z X C V
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    bindings: [Vec<String>; 16],
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let layout = [
+            (0x1, "1"), (0x2, "2"), (0x3, "3"), (0xC, "4"),
+            (0x4, "Q"), (0x5, "W"), (0x6, "E"), (0xD, "R"),
+            (0x7, "A"), (0x8, "S"), (0x9, "D"), (0xE, "F"),
+            (0xA, "Z"), (0x0, "X"), (0xB, "C"), (0xF, "V"),
+        ];
+        let mut keymap = Keymap { bindings: Default::default() };
+        for (key, host_key) in layout {
+            keymap.bindings[key].push(host_key.to_string());
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    /// Makes the given host keys, and only them, press a keypad key. The host keys stop pressing any other key
+    pub fn bind(&mut self, key: u8, host_keys: &[String]) {
+        for binding in self.bindings.iter_mut() {
+            binding.retain(|bound| !host_keys.iter().any(|host_key| host_key.eq_ignore_ascii_case(bound)));
+        }
+        self.bindings[key as usize & 0xF] = host_keys.to_vec();
+    }
+
+    /// The host keys that press the given keypad key
+    pub fn host_keys(&self, key: u8) -> &[String] {
+        &self.bindings[key as usize & 0xF]
+    }
+
+    /// The keypad key pressed by the given host key, if any
+    pub fn key_for(&self, host_key: &str) -> Option<u8> {
+        self.bindings.iter()
+            .position(|binding| binding.iter().any(|bound| bound.eq_ignore_ascii_case(host_key)))
+            .map(|key| key as u8)
+    }
+
+    /// Applies a binding written as `key = host keys`, such as `5 = W, Up`, where the keypad key is a hex digit and
+    /// the host keys are separated by commas
+    pub fn apply_binding(&mut self, text: &str) -> Result<(), String> {
+        let (key, host_keys) = text.split_once('=').ok_or("Expected a binding such as `5 = W, Up`")?;
+        let key = key.trim();
+        let key = match u8::from_str_radix(key, 16) {
+            Ok(digit) if key.len() == 1 => digit,
+            _ => return Err(format!("`{}` is not a keypad key, expected 0 to F", key)),
+        };
+        let host_keys: Vec<String> = host_keys.split(',')
+            .map(|host_key| host_key.trim().to_string())
+            .filter(|host_key| !host_key.is_empty())
+            .collect();
+        self.bind(key, &host_keys);
+        Ok(())
+    }
+
+    /// Applies a key map file to the bindings of the given rom. Each line holds a binding as accepted by
+    /// [`Keymap::apply_binding`], and lines starting with `#` are comments. Bindings before any section apply to every
+    /// rom, while those in a section headed `[name]` only apply when the rom's file name or its CRC-32 in hex is the name
+    pub fn apply_config(&mut self, text: &str, rom_name: &str, rom_hash: u32) -> Result<(), KeymapError> {
+        let rom_hash = format!("{:08x}", rom_hash);
+        let mut applies = true;
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(section) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                let section = section.trim();
+                applies = section == rom_name || section.eq_ignore_ascii_case(&rom_hash);
+                continue;
+            }
+            if applies {
+                self.apply_binding(line).map_err(|message| KeymapError::Parse { line: index + 1, message })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads and applies a key map file, see [`Keymap::apply_config`]
+    pub fn load(&mut self, path: &Path, rom_name: &str, rom_hash: u32) -> Result<(), KeymapError> {
+        self.apply_config(&fs::read_to_string(path)?, rom_name, rom_hash)
+    }
+}
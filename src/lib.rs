@@ -1,8 +1,58 @@
 
+pub mod assembler;
+pub mod audio;
+pub mod debugger;
+pub mod disassembler;
+pub mod display;
 pub mod emulator;
+pub mod filter;
+pub mod frontend;
+pub mod headless;
 pub mod interface;
+pub mod keymap;
+pub mod memory_view;
+pub mod movie;
+pub mod opcodes;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+pub mod save_state;
+pub mod scheduler;
+pub mod timing;
+pub mod trace;
+pub mod trace_diff;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod video;
+#[cfg(feature = "sdl2")]
+pub mod window;
 
 pub mod prelude {
+    pub use super::assembler::*;
+    pub use super::audio::*;
+    pub use super::debugger::*;
+    pub use super::disassembler::*;
+    pub use super::display::*;
     pub use super::emulator::*;
+    pub use super::filter::*;
+    pub use super::frontend::*;
+    pub use super::headless::*;
     pub use super::interface::*;
+    pub use super::keymap::*;
+    pub use super::memory_view::*;
+    pub use super::movie::*;
+    pub use super::opcodes::*;
+    pub use super::quirks::*;
+    pub use super::random::*;
+    pub use super::rewind::*;
+    pub use super::save_state::{SaveStateError, crc32};
+    pub use super::scheduler::*;
+    pub use super::timing::*;
+    pub use super::trace::*;
+    pub use super::trace_diff::*;
+    #[cfg(feature = "tui")]
+    pub use super::tui::*;
+    pub use super::video::*;
+    #[cfg(feature = "sdl2")]
+    pub use super::window::*;
 }
\ No newline at end of file
@@ -1,42 +1,863 @@
-use std::fs;
+use std::{fs, io, process};
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use rustychip::prelude::*;
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum PlatformArg {
+    CosmacVip,
+    Chip48,
+    Schip10,
+    Schip11,
+    Modern,
+    XoChip,
+}
+
+impl From<PlatformArg> for Platform {
+    fn from(arg: PlatformArg) -> Self {
+        match arg {
+            PlatformArg::CosmacVip => Platform::CosmacVip,
+            PlatformArg::Chip48 => Platform::Chip48,
+            PlatformArg::Schip10 => Platform::SuperChip10,
+            PlatformArg::Schip11 => Platform::SuperChip11,
+            PlatformArg::Modern => Platform::Modern,
+            PlatformArg::XoChip => Platform::XoChip,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum WaveformArg {
+    Square,
+    Sine,
+}
+
+impl From<WaveformArg> for Waveform {
+    fn from(arg: WaveformArg) -> Self {
+        match arg {
+            WaveformArg::Square => Waveform::Square,
+            WaveformArg::Sine => Waveform::Sine,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum TimingArg {
+    Fixed,
+    CosmacVip,
+}
+
+impl From<TimingArg> for Timing {
+    fn from(arg: TimingArg) -> Self {
+        match arg {
+            TimingArg::Fixed => Timing::Fixed,
+            TimingArg::CosmacVip => Timing::CosmacVip,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ScalingArg {
+    Fit,
+    Integer,
+}
+
+impl From<ScalingArg> for Scaling {
+    fn from(arg: ScalingArg) -> Self {
+        match arg {
+            ScalingArg::Fit => Scaling::Fit,
+            ScalingArg::Integer => Scaling::Integer,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum FrontendArg {
+    Window,
+    Tui,
+}
 
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
-struct Args {
-    /// The rom file to open
+#[clap(author, version, about, long_about = None, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a rom. This is the default when no command is given
+    Run(Box<RunArgs>),
+    /// Disassemble a rom into CHIP-8 assembly
+    Disasm(DisasmArgs),
+    /// Assemble CHIP-8 assembly into a rom
+    Asm(AsmArgs),
+    /// Print a binary trace written with --trace as text
+    TraceDump(TraceDumpArgs),
+    /// Run a rom without a window and compare its state before every instruction with a trace of another emulator
+    TraceDiff(TraceDiffArgs),
+}
+
+#[derive(Args, Debug)]
+struct TraceDumpArgs {
+    /// The binary trace to print
+    #[clap(value_parser)]
+    trace: String,
+
+    /// The file to write the text to instead of standard output
+    #[clap(short, long, value_parser)]
+    output: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct TraceDiffArgs {
+    /// The rom file to run
+    #[clap(value_parser)]
+    rom: String,
+
+    /// The trace of the other emulator, with a line such as `pc=0200 op=00e0 i=0000 v0=00 ...` per instruction
+    #[clap(value_parser)]
+    reference: String,
+
+    /// Also write the trace of this emulator to this file, in the same format
+    #[clap(short, long, value_parser)]
+    output: Option<String>,
+
+    #[clap(flatten)]
+    emulator: EmulatorArgs,
+}
+
+#[derive(Args, Debug)]
+struct AsmArgs {
+    /// The assembly file to assemble
+    #[clap(value_parser)]
+    input: String,
+
+    /// The rom file to write
+    #[clap(short, long, value_parser)]
+    output: String,
+
+    /// The platform the program is written for, which sets the available instructions
+    #[clap(short, long, value_enum, default_value = "xo-chip")]
+    platform: PlatformArg,
+
+    /// The address the rom is loaded at
+    #[clap(long, value_parser = parse_address, default_value = "0x200")]
+    load_address: u16,
+}
+
+#[derive(Args, Debug)]
+struct DisasmArgs {
+    /// The rom file to disassemble
     #[clap(value_parser)]
     rom: String,
 
-    /// The delay between each emulator step in microseconds
+    /// The platform the rom was written for, which sets the instruction set
+    #[clap(short, long, value_enum, default_value = "modern")]
+    platform: PlatformArg,
+
+    /// The address the rom is loaded at
+    #[clap(long, value_parser = parse_address, default_value = "0x200")]
+    load_address: u16,
+
+    /// The file to write the listing to instead of standard output
+    #[clap(short, long, value_parser)]
+    output: Option<String>,
+}
+
+// The options that set up the emulator, shared by the commands that run roms
+#[derive(Args, Debug)]
+struct EmulatorArgs {
+    /// The time each instruction takes in microseconds, which sets how many run per frame
     #[clap(short, long, value_parser, default_value = "2500")]
     delay: u64,
 
+    /// The number of instructions run per frame, in place of the speed given by the delay
+    #[clap(long, value_parser)]
+    instructions_per_frame: Option<usize>,
+
+    /// The seed of the random number generator, to make runs reproducible. A random seed is used by default
+    #[clap(long, value_parser)]
+    seed: Option<u64>,
+
+    /// How long instructions take. cosmac-vip charges each instruction the machine cycles it takes on the original
+    /// interpreter and runs as many as fit in each frame, ignoring the delay and the instructions per frame
+    #[clap(long, value_enum, default_value = "fixed")]
+    timing: TimingArg,
+
+    /// The platform to emulate, which sets the instruction set and the default value of every quirk
+    #[clap(short, long, value_enum, default_value = "modern")]
+    platform: PlatformArg,
+
     /// Whether to emulate the behaviour of the original chip8 and set vx register to vy and shift it instead of shifting vy in place. Will likely break some roms
-    #[clap(short, long, value_parser, default_value_t = false)]
-    shift_sets_vx: bool,
+    #[clap(short, long, value_parser, min_values = 0, max_values = 1, require_equals = true, default_missing_value = "true")]
+    shift_sets_vx: Option<bool>,
 
     /// Whether to emulate the bug present in CHIP-48 and SUPER-CHIP related to the jump with offset instruction. Will likely break some roms
-    #[clap(short, long, value_parser, default_value_t = false)]
-    jump_with_offset_bug_emulation: bool,
-
+    #[clap(short, long, value_parser, min_values = 0, max_values = 1, require_equals = true, default_missing_value = "true")]
+    jump_with_offset_bug_emulation: Option<bool>,
 
     /// Whether to emulate the behaviour of the original chip8 and increment the I register when storing or loading from memory. Will likely break some roms
-    #[clap(short, long, value_parser, default_value_t = false)]
-    increment_i_on_store_and_load: bool,
+    #[clap(short, long, value_parser, min_values = 0, max_values = 1, require_equals = true, default_missing_value = "true")]
+    increment_i_on_store_and_load: Option<bool>,
+
+    /// Whether the logical instructions 8XY1, 8XY2 and 8XY3 reset VF to 0, as on the original chip8
+    #[clap(long, value_parser, min_values = 0, max_values = 1, require_equals = true, default_missing_value = "true")]
+    vf_reset: Option<bool>,
+
+    /// Whether drawing waits for the vertical blank, limiting sprite draws to 60 per second, as on the original chip8
+    #[clap(long, value_parser, min_values = 0, max_values = 1, require_equals = true, default_missing_value = "true")]
+    display_wait: Option<bool>,
+
+    /// Whether sprites are clipped at the edges of the screen instead of wrapping around
+    #[clap(long, value_parser, min_values = 0, max_values = 1, require_equals = true, default_missing_value = "true")]
+    clip_sprites: Option<bool>,
+
+    /// Whether adding to I sets VF when I overflows past the end of memory
+    #[clap(long, value_parser, min_values = 0, max_values = 1, require_equals = true, default_missing_value = "true")]
+    index_overflow_sets_vf: Option<bool>,
+
+    /// Whether waiting for a key only completes once the key is released, as on the original chip8
+    #[clap(long, value_parser, min_values = 0, max_values = 1, require_equals = true, default_missing_value = "true")]
+    key_wait_on_release: Option<bool>,
+}
+
+impl EmulatorArgs {
+    // The quirks of the selected platform, with any quirk given on the command line overridden
+    fn quirks(&self) -> Quirks {
+        let mut quirks = Platform::from(self.platform).quirks();
+        let overrides = [
+            (&mut quirks.shift_sets_vx, self.shift_sets_vx),
+            (&mut quirks.jump_with_offset_bug_emulation, self.jump_with_offset_bug_emulation),
+            (&mut quirks.increment_i_on_store_and_load, self.increment_i_on_store_and_load),
+            (&mut quirks.vf_reset, self.vf_reset),
+            (&mut quirks.display_wait, self.display_wait),
+            (&mut quirks.clip_sprites, self.clip_sprites),
+            (&mut quirks.index_overflow_sets_vf, self.index_overflow_sets_vf),
+            (&mut quirks.key_wait_on_release, self.key_wait_on_release),
+        ];
+        for (quirk, value) in overrides {
+            if let Some(value) = value {
+                *quirk = value;
+            }
+        }
+        quirks
+    }
+
+    // One frame lasts 1/60th of a second, so this matches the speed of a windowed run with the same delay
+    fn instructions_per_frame(&self) -> usize {
+        self.instructions_per_frame.unwrap_or_else(|| (1_000_000 / 60 / self.delay.max(1)).max(1) as usize)
+    }
+
+    fn builder(&self, rom: &[u8]) -> EmulatorBuilder {
+        let mut builder = Emulator::builder(rom)
+            .platform(self.platform.into())
+            .quirks(self.quirks())
+            .timing(self.timing.into());
+        if let Some(seed) = self.seed {
+            builder = builder.seed(seed);
+        }
+        builder
+    }
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
+    /// The rom file to open
+    #[clap(value_parser, required = true)]
+    rom: Option<String>,
+
+    /// Where to show the emulator: an SDL window, or the terminal for when no window can be opened
+    #[clap(long, value_enum, default_value = "window")]
+    frontend: FrontendArg,
+
+    #[clap(flatten)]
+    emulator: EmulatorArgs,
+
+    /// How fast emulated time passes, where 1 is real time. Can be changed while running with - and =
+    #[clap(long, value_parser = parse_speed, default_value = "1")]
+    speed: f64,
+
+    /// The speed while tab is held to fast forward
+    #[clap(long, value_parser = parse_speed, default_value = "4")]
+    fast_forward: f64,
+
+    /// A save state file to restore before starting, such as one written by a quick save
+    #[clap(long, value_parser)]
+    load_state: Option<String>,
+
+    /// How many seconds of gameplay can be rewound by holding backspace. 0 disables rewinding
+    #[clap(long, value_parser, default_value = "10")]
+    rewind_seconds: usize,
+
+    /// The maximum memory used by the rewind history in MiB
+    #[clap(long, value_parser, default_value = "32")]
+    rewind_budget: usize,
+
+    /// Start paused under the debugger, controlled through a command prompt on the terminal
+    #[clap(long, value_parser, default_value_t = false)]
+    debug: bool,
+
+    /// Start with the memory view open, which F2 opens and closes
+    #[clap(long, value_parser, default_value_t = false)]
+    memory_view: bool,
+
+    /// Don't play any sound
+    #[clap(long, value_parser, default_value_t = false)]
+    mute: bool,
+
+    /// The shape of the tone played while the sound timer runs
+    #[clap(long, value_enum, default_value = "square")]
+    waveform: WaveformArg,
+
+    /// The frequency of the tone in Hz
+    #[clap(long, value_parser, default_value = "440")]
+    frequency: f32,
+
+    /// The volume of the tone, from 0 to 1
+    #[clap(long, value_parser = parse_volume, default_value = "0.25")]
+    volume: f32,
+
+    /// Run without a window for a number of frames or until the program halts, then print the display and registers
+    #[clap(long, value_parser, default_value_t = false)]
+    headless: bool,
+
+    /// The maximum number of frames to run in headless mode
+    #[clap(long, value_parser, default_value = "600")]
+    frames: u64,
+
+    /// Stop a headless run when this address is reached. Can be given more than once
+    #[clap(long = "break", value_parser = parse_address, multiple_occurrences = true)]
+    breakpoints: Vec<u16>,
+
+    /// A key map file binding keypad keys to keyboard keys, with lines such as `5 = W, Up` and optional sections
+    /// headed by a rom's file name or CRC-32 that only apply to that rom
+    #[clap(long, value_parser)]
+    keymap: Option<String>,
+
+    /// Bind a keypad key to keyboard keys, such as `5=W,Up`, after the key map file. Can be given more than once
+    #[clap(long = "bind", value_parser, multiple_occurrences = true)]
+    bindings: Vec<String>,
+
+    /// Record every key press into this movie file, written when the window closes
+    #[clap(long, value_parser)]
+    record_movie: Option<String>,
+
+    /// Play back a movie file in place of the keyboard, checking that the display ends up the same as when it was
+    /// recorded. The platform, quirks and seed are taken from the movie
+    #[clap(long, value_parser)]
+    play_movie: Option<String>,
+
+    /// Write every instruction that runs to this file, with the registers it changed and the index register after it.
+    /// Files ending in .txt or .log are written as text, others in a compact binary format printed by `trace-dump`
+    #[clap(long, value_parser)]
+    trace: Option<String>,
+
+    /// Only trace instructions at addresses in this range, such as `0x200-0x2ff`. Can be given more than once
+    #[clap(long, value_parser = parse_address_range, multiple_occurrences = true)]
+    trace_range: Vec<RangeInclusive<u16>>,
+
+    /// Only trace instructions of this class: flow, arithmetic, memory, display, input or timer. Can be given more
+    /// than once
+    #[clap(long, value_parser = parse_opcode_class, multiple_occurrences = true)]
+    trace_class: Vec<OpcodeClass>,
+
+    /// Stop tracing after this many instructions were traced
+    #[clap(long, value_parser)]
+    trace_limit: Option<u64>,
+
+    /// Write the display to this PNG file at the end of a headless run
+    #[clap(long, value_parser)]
+    png: Option<String>,
+
+    /// Record every frame to this file, as an animated GIF if it ends in .gif and as raw RGB frames for ffmpeg otherwise
+    #[clap(long, value_parser)]
+    record_video: Option<String>,
+
+    /// The colours to draw with: default, xo-chip, green or amber, or two to four hex colours such as `000000,ffffff`
+    /// for unset pixels, pixels set in the first plane, in the second plane and in both
+    #[clap(long, value_parser = parse_palette, default_value = "default")]
+    palette: Palette,
+
+    /// Keep pixels glowing after they turn off, keeping this share of their brightness every frame, such as 0.7. Hides
+    /// the flicker of sprites erased and drawn again
+    #[clap(long, value_parser = parse_persistence, default_value = "0")]
+    persistence: f32,
+
+    /// Average every frame with the one before, so pixels that flicker show at half brightness
+    #[clap(long, value_parser, default_value_t = false)]
+    blend: bool,
+
+    /// Only show the display at the end of a frame once the program finished drawing it, keeping the last finished
+    /// display while sprites are erased but not drawn again yet
+    #[clap(long, value_parser, default_value_t = false)]
+    vblank: bool,
+
+    /// How the display is scaled to the window: as large as fits, or by a whole number for evenly sized pixels
+    #[clap(long, value_enum, default_value = "fit")]
+    scaling: ScalingArg,
+
+    /// Start the window in full screen. F11 switches between the window and full screen
+    #[clap(long, value_parser, default_value_t = false)]
+    fullscreen: bool,
+
+    /// The size of a low resolution pixel in screenshots and videos, so that they are 64 times as wide
+    #[clap(long, value_parser = parse_capture_scale, default_value = "10")]
+    capture_scale: usize,
+
+    /// Capture the sound of a headless run to this WAV file
+    #[clap(long, value_parser)]
+    wav: Option<String>,
+}
+
+impl RunArgs {
+    // The display filter with the palette, persistence, blending and vblank options
+    fn filter(&self) -> DisplayFilter {
+        let mut filter = DisplayFilter::new(self.palette).with_persistence(self.persistence);
+        if self.blend {
+            filter = filter.with_blending();
+        }
+        if self.vblank {
+            filter = filter.with_vblank();
+        }
+        filter
+    }
+
+    fn trace_filter(&self) -> TraceFilter {
+        let mut filter = TraceFilter::new();
+        for range in &self.trace_range {
+            filter = filter.with_range(range.clone());
+        }
+        for class in &self.trace_class {
+            filter = filter.with_class(*class);
+        }
+        if let Some(limit) = self.trace_limit {
+            filter = filter.with_limit(limit);
+        }
+        filter
+    }
+
+    fn audio(&self) -> AudioConfig {
+        AudioConfig {
+            waveform: self.waveform.into(),
+            frequency: self.frequency,
+            volume: self.volume,
+        }
+    }
+}
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    let result = match text.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    result.map_err(|e| e.to_string())
+}
+
+fn parse_address_range(text: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = text.split_once('-').ok_or("Expected a range such as 0x200-0x2ff")?;
+    let (start, end) = (parse_address(start)?, parse_address(end)?);
+    if start > end {
+        return Err("The range ends before it starts".to_string());
+    }
+    Ok(start..=end)
+}
+
+fn parse_opcode_class(text: &str) -> Result<OpcodeClass, String> {
+    text.parse()
+}
+
+fn parse_volume(text: &str) -> Result<f32, String> {
+    match text.parse::<f32>() {
+        Ok(volume) if (0.0..=1.0).contains(&volume) => Ok(volume),
+        Ok(_) => Err("The volume must be between 0 and 1".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_speed(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(speed) if (MIN_SPEED..=MAX_SPEED).contains(&speed) => Ok(speed),
+        Ok(_) => Err(format!("The speed must be between {} and {}", MIN_SPEED, MAX_SPEED)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_palette(text: &str) -> Result<Palette, String> {
+    text.parse()
+}
+
+fn parse_persistence(text: &str) -> Result<f32, String> {
+    match text.parse::<f32>() {
+        Ok(decay) if (0.0..=MAX_PERSISTENCE).contains(&decay) => Ok(decay),
+        Ok(_) => Err(format!("The persistence must be between 0 and {}", MAX_PERSISTENCE)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_capture_scale(text: &str) -> Result<usize, String> {
+    match text.parse::<usize>() {
+        Ok(scale) if (1..=64).contains(&scale) => Ok(scale),
+        Ok(_) => Err("The scale must be between 1 and 64".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
 }
 
 fn main() {
-    let args = Args::parse();
-    let rom = match fs::read(args.rom) {
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Run(args)) => run(*args),
+        Some(Command::Disasm(args)) => disasm(args),
+        Some(Command::Asm(args)) => asm(args),
+        Some(Command::TraceDump(args)) => trace_dump(args),
+        Some(Command::TraceDiff(args)) => trace_diff(args),
+        None => run(cli.run),
+    }
+}
+
+fn asm(args: AsmArgs) {
+    let assembler = Assembler::new(Platform::from(args.platform).instruction_set(), args.load_address);
+    let rom = match assembler.assemble_file(Path::new(&args.input)) {
+        Ok(rom) => rom,
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = fs::write(&args.output, rom) {
+        println!("Error writing rom: {}", e);
+    }
+}
+
+fn disasm(args: DisasmArgs) {
+    let rom = match fs::read(&args.rom) {
+        Ok(rom) => rom,
+        Err(e) => {
+            println!("Error reading rom: {}", e);
+            return;
+        }
+    };
+    let listing = Disassembler::new(&rom, args.load_address, Platform::from(args.platform).instruction_set()).disassemble();
+    match &args.output {
+        Some(path) => {
+            if let Err(e) = fs::write(path, listing) {
+                println!("Error writing listing: {}", e);
+            }
+        }
+        None => print!("{}", listing),
+    }
+}
+
+fn trace_dump(args: TraceDumpArgs) {
+    let reader = match TraceReader::open(Path::new(&args.trace)) {
+        Ok(reader) => reader,
+        Err(e) => {
+            println!("Error reading trace: {}", e);
+            return;
+        }
+    };
+    let output: Box<dyn Write> = match &args.output {
+        Some(path) => match fs::File::create(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                println!("Error creating {}: {}", path, e);
+                return;
+            }
+        },
+        None => Box::new(io::stdout()),
+    };
+    let mut output = io::BufWriter::new(output);
+    let instruction_set = reader.instruction_set();
+    for entry in reader {
+        let result = match entry {
+            Ok(entry) => writeln!(output, "{}", entry.format(instruction_set)),
+            Err(e) => {
+                println!("Error reading trace: {}", e);
+                break;
+            }
+        };
+        // Stops quietly when the output is closed early, such as by head
+        if result.is_err() {
+            return;
+        }
+    }
+    if let Err(e) = output.flush() {
+        println!("Error writing text: {}", e);
+    }
+}
+
+fn trace_diff(args: TraceDiffArgs) {
+    let rom = match fs::read(&args.rom) {
+        Ok(rom) => rom,
+        Err(e) => {
+            println!("Error reading rom: {}", e);
+            return;
+        }
+    };
+    let mut emu = match args.emulator.builder(&rom).build() {
+        Ok(emu) => emu,
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    };
+    let reference = match fs::File::open(&args.reference) {
+        Ok(file) => io::BufReader::new(file),
+        Err(e) => {
+            println!("Error reading {}: {}", args.reference, e);
+            return;
+        }
+    };
+    let mut output = match &args.output {
+        Some(path) => match fs::File::create(path) {
+            Ok(file) => Some(io::BufWriter::new(file)),
+            Err(e) => {
+                println!("Error creating {}: {}", path, e);
+                return;
+            }
+        },
+        None => None,
+    };
+    let result = diff_trace(&mut emu, args.emulator.instructions_per_frame(), reference, output.as_mut().map(|output| output as &mut dyn Write));
+    if let Some(Err(e)) = output.as_mut().map(|output| output.flush()) {
+        println!("Error writing trace: {}", e);
+    }
+    match result {
+        Ok(TraceDiff::Matched { instructions }) => println!("The traces match for all {} instructions", instructions),
+        Ok(TraceDiff::Diverged(divergence)) => {
+            print!("{}", divergence.report(emu.instruction_set()));
+            process::exit(1);
+        }
+        Ok(TraceDiff::Stopped { instructions, line, reason }) => {
+            println!("The emulator stopped after {} instructions, at line {} of the reference: {}", instructions, line, reason);
+            process::exit(1);
+        }
+        Err(e) => println!("Error: {}", e),
+    }
+}
+
+fn run(args: RunArgs) {
+    // The rom is always present, clap only leaves it out when a subcommand is given instead
+    let rom_path = args.rom.clone().unwrap();
+    let rom = match fs::read(&rom_path) {
         Ok(rom) => rom,
         Err(e) => {
             println!("Error reading rom: {}", e);
             return;
         }
     };
-    let emu = Emulator::new(&rom, args.shift_sets_vx, args.jump_with_offset_bug_emulation, args.increment_i_on_store_and_load).unwrap();
-    let interface = Interface::new(emu, args.delay);
-    interface.run();
-}
\ No newline at end of file
+    let movie = match &args.play_movie {
+        Some(path) => match Movie::load(Path::new(path)) {
+            Ok(movie) => Some(movie),
+            Err(e) => {
+                println!("Error loading movie: {}", e);
+                return;
+            }
+        },
+        None => None,
+    };
+    if (movie.is_some() || args.record_movie.is_some()) && (args.load_state.is_some() || args.debug) {
+        println!("Movies always start from the beginning of the rom and can't be used with --load-state or --debug");
+        return;
+    }
+    if args.frontend == FrontendArg::Tui && args.debug {
+        println!("The debugger reads commands from the terminal, so it is only available in the window frontend");
+        return;
+    }
+    if args.record_movie.is_some() && (movie.is_some() || args.headless) {
+        println!("Movies can only be recorded from the keyboard, without --headless or --play-movie");
+        return;
+    }
+
+    let emu = match &movie {
+        Some(movie) => movie.emulator(&rom).map_err(|e| e.to_string()),
+        None => {
+            args.emulator.builder(&rom).build().map_err(|e| e.to_string())
+        }
+    };
+    let mut emu = match emu {
+        Ok(emu) => emu,
+        Err(e) => {
+            println!("Error loading rom: {}", e);
+            return;
+        }
+    };
+    if let Some(path) = &args.load_state {
+        let state = match fs::read(path) {
+            Ok(state) => state,
+            Err(e) => {
+                println!("Error reading save state: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = emu.load_state(&state) {
+            println!("Error loading save state: {}", e);
+            return;
+        }
+    }
+    let video = match &args.record_video {
+        Some(path) => match create_video_sink(Path::new(path), &args.palette, args.capture_scale) {
+            Ok(sink) => Some(sink),
+            Err(e) => {
+                println!("Error creating video file: {}", e);
+                return;
+            }
+        },
+        None => None,
+    };
+    let tracer = match &args.trace {
+        Some(path) => match create_trace_sink(Path::new(path), emu.instruction_set()) {
+            Ok(sink) => Some(Tracer::new(sink, args.trace_filter())),
+            Err(e) => {
+                println!("Error creating trace file: {}", e);
+                return;
+            }
+        },
+        None => None,
+    };
+    if args.headless {
+        headless(emu, &args, movie, video, tracer);
+        return;
+    }
+    let mut keymap = Keymap::default();
+    if let Some(path) = &args.keymap {
+        let rom_name = Path::new(&rom_path).file_name().unwrap_or_default().to_string_lossy();
+        if let Err(e) = keymap.load(Path::new(path), &rom_name, emu.rom_hash()) {
+            println!("Error loading key map {}: {}", path, e);
+            return;
+        }
+    }
+    for binding in &args.bindings {
+        if let Err(e) = keymap.apply_binding(binding) {
+            println!("Invalid key binding {}: {}", binding, e);
+            return;
+        }
+    }
+    let recording = args.record_movie.as_ref()
+        .map(|path| (Movie::new(&emu, args.emulator.instructions_per_frame() as u32), PathBuf::from(path)));
+    let instructions_per_frame = movie.as_ref()
+        .map_or(args.emulator.instructions_per_frame(), |movie| movie.instructions_per_frame as usize);
+    let mut scheduler = Scheduler::new();
+    scheduler.set_speed(args.speed);
+    scheduler.set_fast_forward_speed(args.fast_forward);
+    let mut interface = Interface::new(emu, instructions_per_frame)
+        .with_state_path(PathBuf::from(&rom_path))
+        .with_keymap(keymap)
+        .with_scheduler(scheduler)
+        .with_filter(args.filter())
+        .with_screenshots(args.palette, args.capture_scale);
+    if let Some(sink) = video {
+        interface = interface.with_video(sink);
+    }
+    if let Some(tracer) = tracer {
+        interface = interface.with_tracer(tracer);
+    }
+    if let Some((movie, path)) = recording {
+        interface = interface.with_movie_recording(movie, path);
+    }
+    if let Some(movie) = movie {
+        interface = interface.with_movie_playback(movie);
+    }
+    if args.debug {
+        interface = interface.with_debugger(Debugger::new());
+    }
+    if args.memory_view {
+        interface = interface.with_memory_view();
+    }
+    if args.rewind_seconds > 0 {
+        interface = interface.with_rewind(Rewind::new(args.rewind_seconds * 60, args.rewind_budget * 1024 * 1024));
+    }
+    match args.frontend {
+        FrontendArg::Window => run_window(interface, &args),
+        FrontendArg::Tui => match Tui::new() {
+            Ok(tui) if args.mute => interface.run(tui),
+            Ok(tui) => interface.run(tui.with_bell()),
+            Err(e) => println!("Error starting the terminal frontend: {}", e),
+        },
+    }
+}
+
+#[cfg(feature = "sdl2")]
+fn run_window(interface: Interface, args: &RunArgs) {
+    let window = match Window::new((!args.mute).then(|| args.audio())) {
+        Ok(window) => window.with_scaling(args.scaling.into()),
+        Err(e) => {
+            println!("Error opening the window: {}", e);
+            return;
+        }
+    };
+    if args.fullscreen {
+        interface.run(window.with_fullscreen());
+    } else {
+        interface.run(window);
+    }
+}
+
+#[cfg(not(feature = "sdl2"))]
+fn run_window(_interface: Interface, _args: &RunArgs) {
+    println!("This build has no window, run with --frontend tui or --headless instead");
+}
+
+fn headless(emu: Emulator, args: &RunArgs, movie: Option<Movie>, video: Option<Box<dyn VideoSink>>, tracer: Option<Tracer>) {
+    let mut runner = match movie {
+        Some(movie) => HeadlessRunner::new(emu, movie.instructions_per_frame as usize).with_movie(movie),
+        None => HeadlessRunner::new(emu, args.emulator.instructions_per_frame()),
+    };
+    if let Some(path) = &args.wav {
+        match WavSink::create(Path::new(path), SAMPLE_RATE) {
+            Ok(sink) => runner = runner.with_audio(args.audio(), Box::new(sink)),
+            Err(e) => {
+                println!("Error creating wav file: {}", e);
+                return;
+            }
+        }
+    }
+    if let Some(sink) = video {
+        runner = runner.with_video(sink);
+    }
+    if let Some(tracer) = tracer {
+        runner = runner.with_tracer(tracer);
+    }
+    for address in &args.breakpoints {
+        runner.debugger_mut().add_breakpoint(*address);
+    }
+    let frames = runner.movie().map_or(args.frames, |movie| movie.frames);
+    let reason = runner.run(frames);
+    if let Err(e) = runner.finish_audio() {
+        println!("Error writing wav file: {}", e);
+    }
+    if let Err(e) = runner.finish_video() {
+        println!("Error writing video: {}", e);
+    }
+    if let Err(e) = runner.finish_trace() {
+        println!("Error writing trace: {}", e);
+    }
+
+    let emulator = runner.emulator();
+    println!("{} after {} frames with seed {}", reason, runner.frame(), emulator.random().seed());
+    print!("{}", render_text(emulator));
+    println!("{}", format_registers(emulator));
+    let movie_matches = runner.movie().is_none_or(|movie| movie.verify(emulator));
+    if runner.movie().is_some() {
+        if movie_matches {
+            println!("The display matches the end of the movie");
+        } else {
+            println!("The display does not match the end of the movie");
+        }
+    }
+    if let Some(path) = &args.png {
+        let result = fs::File::create(path).map_err(|e| e.to_string())
+            .and_then(|file| write_png(emulator, io::BufWriter::new(file), &args.palette, args.capture_scale).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            println!("Error writing png: {}", e);
+        }
+    }
+    if matches!(reason, HaltReason::Stopped(StopReason::Error(_))) || !movie_matches {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use crate::display::Frame;
+use crate::emulator::{Emulator, FONT, MemoryAccess};
+
+
+/// Bytes shown in each row of a [`MemoryView`]
+pub const BYTES_PER_ROW: usize = 16;
+
+/// Rows shown at once by a [`MemoryView`]
+pub const VIEW_ROWS: usize = 16;
+
+// How long written bytes stay highlighted, in frames
+const WRITE_HIGHLIGHT_FRAMES: u64 = 60;
+
+// Rows shown above the program counter while following it
+const ROWS_BEFORE_PC: usize = 4;
+
+// Characters in each row of the view: the address, two spaces, and the bytes separated by spaces
+const ROW_CHARACTERS: usize = 4 + 2 + BYTES_PER_ROW * 3 - 1;
+
+/// Why a byte stands out in the memory view. A byte that is several of these is highlighted as the first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Highlight {
+    None,
+    /// The byte being edited while paused
+    Cursor,
+    /// One of the two bytes of the instruction about to run
+    Pc,
+    /// The byte the index register points to
+    Index,
+    /// A byte the program wrote in the last second, such as with FX33 or FX55
+    Written,
+}
+
+impl Highlight {
+    /// The colours of the byte's digits and of the background behind them
+    pub fn colors(self) -> ([u8; 3], [u8; 3]) {
+        match self {
+            Highlight::None => ([0xaa, 0xaa, 0xaa], [0x00, 0x00, 0x00]),
+            Highlight::Cursor => ([0x00, 0x00, 0x00], [0xff, 0xff, 0xff]),
+            Highlight::Pc => ([0x00, 0x00, 0x00], [0x33, 0xcc, 0x55]),
+            Highlight::Index => ([0x00, 0x00, 0x00], [0x44, 0x99, 0xff]),
+            Highlight::Written => ([0x00, 0x00, 0x00], [0xff, 0x99, 0x33]),
+        }
+    }
+}
+
+/// A row of the memory view, starting at the given address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryRow {
+    pub address: u16,
+    pub bytes: Vec<(u8, Highlight)>,
+}
+
+impl MemoryRow {
+    /// The row as text, such as `0200  00 e0 a2 2a ...`
+    pub fn text(&self) -> String {
+        let bytes: Vec<String> = self.bytes.iter().map(|(value, _)| format!("{:02x}", value)).collect();
+        format!("{:04x}  {}", self.address, bytes.join(" "))
+    }
+}
+
+/// A live hex view of the emulator's memory, [`VIEW_ROWS`] rows of [`BYTES_PER_ROW`] bytes at a time. It follows the
+/// program counter until it is scrolled, and highlights the instruction about to run, the byte the index register
+/// points to and the bytes the program wrote recently. While the emulator is paused, a cursor can be moved to overwrite
+/// bytes by typing hex digits.
+///
+/// The view only sees the writes of steps after which [`MemoryView::record_writes`] is called, with the emulator
+/// recording memory accesses.
+pub struct MemoryView {
+    // The first address shown when not following the program counter, at the start of a row
+    top: usize,
+    following: bool,
+    cursor: usize,
+    // Whether the next digit typed sets the low half of the byte under the cursor
+    low_digit: bool,
+    // The frame each recently written byte was last written in
+    written: HashMap<u16, u64>,
+    frame: u64,
+}
+
+impl Default for MemoryView {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryView {
+    pub fn new() -> MemoryView {
+        MemoryView {
+            top: 0,
+            following: true,
+            cursor: 0,
+            low_digit: false,
+            written: HashMap::new(),
+            frame: 0,
+        }
+    }
+
+    /// Notes the bytes written by the last step
+    pub fn record_writes(&mut self, emulator: &Emulator) {
+        for access in emulator.memory_accesses() {
+            if let MemoryAccess::Write(address) = access {
+                self.written.insert(*address, self.frame);
+            }
+        }
+    }
+
+    /// Ages the highlights of written bytes by a frame
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+        let frame = self.frame;
+        self.written.retain(|_, written| frame - *written < WRITE_HIGHLIGHT_FRAMES);
+    }
+
+    /// Scrolls by the given number of rows, which stops following the program counter
+    pub fn scroll(&mut self, emulator: &Emulator, rows: isize) {
+        let top = self.top(emulator) as isize + rows * BYTES_PER_ROW as isize;
+        self.top = top.clamp(0, Self::last_top(emulator) as isize) as usize;
+        self.following = false;
+    }
+
+    /// Follows the program counter again
+    pub fn follow(&mut self) {
+        self.following = true;
+    }
+
+    /// Moves the cursor by the given number of bytes, scrolling to keep it in view. The cursor starts on the program
+    /// counter
+    pub fn move_cursor(&mut self, emulator: &Emulator, offset: isize) {
+        self.stop_following(emulator);
+        self.cursor = (self.cursor as isize + offset).clamp(0, emulator.memory_size() as isize - 1) as usize;
+        self.low_digit = false;
+        let row = self.cursor - self.cursor % BYTES_PER_ROW;
+        let last_row = self.top + (VIEW_ROWS - 1) * BYTES_PER_ROW;
+        if row < self.top {
+            self.top = row;
+        } else if row > last_row {
+            self.top = row - (VIEW_ROWS - 1) * BYTES_PER_ROW;
+        }
+    }
+
+    /// Overwrites half of the byte under the cursor with a hex digit, the high half first. The cursor moves on to the
+    /// next byte once both are typed
+    pub fn type_digit(&mut self, emulator: &mut Emulator, digit: u8) {
+        self.stop_following(emulator);
+        let address = self.cursor as u16;
+        let value = emulator.peek(address);
+        let value = match self.low_digit {
+            false => digit << 4 | value & 0x0F,
+            true => value & 0xF0 | digit & 0x0F,
+        };
+        emulator.poke(address, value);
+        self.written.insert(address, self.frame);
+        match self.low_digit {
+            false => self.low_digit = true,
+            true => self.move_cursor(emulator, 1),
+        }
+    }
+
+    /// The rows currently in view, showing the cursor if editing
+    pub fn rows(&self, emulator: &Emulator, editing: bool) -> Vec<MemoryRow> {
+        let memory = emulator.memory();
+        let top = self.top(emulator);
+        (top..memory.len()).step_by(BYTES_PER_ROW).take(VIEW_ROWS).map(|address| MemoryRow {
+            address: address as u16,
+            bytes: (address..(address + BYTES_PER_ROW).min(memory.len())).map(|address| {
+                (memory[address], self.highlight(emulator, address, editing))
+            }).collect(),
+        }).collect()
+    }
+
+    fn highlight(&self, emulator: &Emulator, address: usize, editing: bool) -> Highlight {
+        let pc = emulator.pc() as usize % emulator.memory_size();
+        if editing && !self.following && address == self.cursor {
+            Highlight::Cursor
+        } else if address == pc || address == (pc + 1) % emulator.memory_size() {
+            Highlight::Pc
+        } else if address == emulator.index() as usize % emulator.memory_size() {
+            Highlight::Index
+        } else if self.written.contains_key(&(address as u16)) {
+            Highlight::Written
+        } else {
+            Highlight::None
+        }
+    }
+
+    fn top(&self, emulator: &Emulator) -> usize {
+        if !self.following {
+            return self.top;
+        }
+        let pc = emulator.pc() as usize % emulator.memory_size();
+        let top = (pc - pc % BYTES_PER_ROW).saturating_sub(ROWS_BEFORE_PC * BYTES_PER_ROW);
+        top.min(Self::last_top(emulator))
+    }
+
+    fn last_top(emulator: &Emulator) -> usize {
+        emulator.memory_size().saturating_sub(VIEW_ROWS * BYTES_PER_ROW)
+    }
+
+    // Leaves the view where it is, with the cursor on the program counter
+    fn stop_following(&mut self, emulator: &Emulator) {
+        if self.following {
+            self.top = self.top(emulator);
+            self.cursor = emulator.pc() as usize % emulator.memory_size();
+            self.low_digit = false;
+            self.following = false;
+        }
+    }
+}
+
+/// Draws rows of the memory view as a picture, with the hex digits of the CHIP-8 font, for frontends that can't show
+/// text. Every character is 5 pixels wide and every row 7 pixels high, with spacing included
+pub fn render_rows(rows: &[MemoryRow]) -> Frame {
+    let (width, height) = (ROW_CHARACTERS * 5, rows.len() * 7);
+    let mut pixels = vec![Highlight::None.colors().1; width * height];
+    for (row_number, row) in rows.iter().enumerate() {
+        let y = row_number * 7;
+        let (color, _) = Highlight::None.colors();
+        for (place, shift) in [12, 8, 4, 0].into_iter().enumerate() {
+            draw_digit(&mut pixels, width, place * 5, y, (row.address >> shift & 0xF) as u8, color);
+        }
+        for (column, (value, highlight)) in row.bytes.iter().enumerate() {
+            let x = (6 + column * 3) * 5;
+            let (color, background) = highlight.colors();
+            // The background leaves a pixel of space around the digits
+            for line in y..y + 7 {
+                pixels[line * width + x - 1..line * width + x + 10].fill(background);
+            }
+            draw_digit(&mut pixels, width, x, y, value >> 4, color);
+            draw_digit(&mut pixels, width, x + 5, y, value & 0xF, color);
+        }
+    }
+    Frame { width, height, pixels }
+}
+
+// Draws a hex digit with its top left corner a pixel below the given position
+fn draw_digit(pixels: &mut [[u8; 3]], width: usize, x: usize, y: usize, digit: u8, color: [u8; 3]) {
+    for (dy, bits) in FONT[digit as usize * 5..digit as usize * 5 + 5].iter().enumerate() {
+        for dx in (0..4).filter(|dx| bits & 0x80 >> dx != 0) {
+            pixels[(y + 1 + dy) * width + x + dx] = color;
+        }
+    }
+}
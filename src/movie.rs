@@ -0,0 +1,284 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use thiserror::Error;
+use crate::display;
+use crate::emulator::{Emulator, EmulatorError, InstructionSet};
+use crate::quirks::Quirks;
+use crate::save_state::{self, SaveStateError, StateReader, StateWriter};
+use crate::timing::Timing;
+
+
+// Movies start with this magic and the format version. Everything is followed by a checksum of what came before
+const MAGIC: [u8; 4] = *b"RCMV";
+pub const MOVIE_VERSION: u16 = 2;
+
+#[derive(Error, Debug)]
+pub enum MovieError {
+    #[error("The data is not a rustychip movie")]
+    InvalidMagic,
+    #[error("Movie version {0} is not supported, expected version {MOVIE_VERSION}")]
+    UnsupportedVersion(u16),
+    #[error("The movie is corrupted, its checksum does not match")]
+    ChecksumMismatch,
+    #[error("The movie was recorded with a different rom")]
+    RomMismatch,
+    #[error("The movie ended unexpectedly")]
+    Truncated,
+    #[error("The movie contains an invalid value: {0}")]
+    InvalidData(&'static str),
+    #[error("Error building the emulator: {0}")]
+    Emulator(#[from] EmulatorError),
+    #[error("Error accessing the movie file: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl From<SaveStateError> for MovieError {
+    fn from(error: SaveStateError) -> Self {
+        match error {
+            SaveStateError::Truncated => MovieError::Truncated,
+            SaveStateError::InvalidData(what) => MovieError::InvalidData(what),
+            _ => MovieError::InvalidData("state"),
+        }
+    }
+}
+
+/// A change of a keypad key, applied before the instructions of the given frame run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// A recording of every keypad change of a run, along with everything needed to start the same run again: the rom
+/// hash, the instruction set, the quirks, the random seed, the timing model and the number of instructions per frame.
+///
+/// The hash of the display at the end of the recording is kept, so that a replay can verify it ended the same way.
+#[derive(Debug, Clone)]
+pub struct Movie {
+    pub rom_hash: u32,
+    pub instruction_set: InstructionSet,
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub timing: Timing,
+    pub instructions_per_frame: u32,
+    pub events: Vec<KeyEvent>,
+    /// The number of frames recorded
+    pub frames: u64,
+    /// The hash of the display after the last frame
+    pub display_hash: u32,
+}
+
+impl Movie {
+    /// Starts a recording of a run of the given emulator, which must not have run yet
+    pub fn new(emulator: &Emulator, instructions_per_frame: u32) -> Movie {
+        Movie {
+            rom_hash: emulator.rom_hash(),
+            instruction_set: emulator.instruction_set(),
+            quirks: emulator.quirks(),
+            seed: emulator.random().seed(),
+            timing: emulator.timing(),
+            instructions_per_frame,
+            events: Vec::new(),
+            frames: 0,
+            display_hash: display::display_hash(emulator),
+        }
+    }
+
+    pub fn record(&mut self, frame: u64, key: u8, pressed: bool) {
+        self.events.push(KeyEvent { frame, key, pressed });
+    }
+
+    /// Ends the recording after the given number of frames, keeping the hash of the final display
+    pub fn finish(&mut self, frames: u64, emulator: &Emulator) {
+        self.frames = frames;
+        self.display_hash = display::display_hash(emulator);
+    }
+
+    /// The key changes to apply before the given frame
+    pub fn events_at(&self, frame: u64) -> impl Iterator<Item = &KeyEvent> {
+        let start = self.events.partition_point(|event| event.frame < frame);
+        self.events[start..].iter().take_while(move |event| event.frame == frame)
+    }
+
+    /// Whether the emulator shows the same display as at the end of the recording
+    pub fn verify(&self, emulator: &Emulator) -> bool {
+        display::display_hash(emulator) == self.display_hash
+    }
+
+    /// Builds an emulator for the given rom set up exactly as the recorded one was
+    pub fn emulator(&self, rom: &[u8]) -> Result<Emulator, MovieError> {
+        if save_state::crc32(rom) != self.rom_hash {
+            return Err(MovieError::RomMismatch);
+        }
+        let emulator = Emulator::builder(rom)
+            .instruction_set(self.instruction_set)
+            .quirks(self.quirks)
+            .seed(self.seed)
+            .timing(self.timing)
+            .build()?;
+        Ok(emulator)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.bytes(&MAGIC);
+        writer.u16(MOVIE_VERSION);
+        writer.u32(self.rom_hash);
+        writer.u8(self.instruction_set as u8);
+        writer.u16(self.quirks.to_bits());
+        // The random algorithm, of which xorshift is the only one
+        writer.u8(0);
+        writer.u64(self.seed);
+        writer.u8(self.timing as u8);
+        writer.u32(self.instructions_per_frame);
+        writer.u64(self.frames);
+        writer.u32(self.display_hash);
+        writer.u32(self.events.len() as u32);
+        for event in self.events.iter() {
+            writer.u64(event.frame);
+            writer.u8(event.key | (event.pressed as u8) << 7);
+        }
+        let mut data = writer.finish();
+        let checksum = save_state::crc32(&data);
+        data.extend_from_slice(&checksum.to_le_bytes());
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, MovieError> {
+        if data.len() < MAGIC.len() + 4 {
+            return Err(MovieError::Truncated);
+        }
+        let (body, checksum) = data.split_at(data.len() - 4);
+        let mut reader = StateReader::new(body);
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(MovieError::InvalidMagic);
+        }
+        let version = reader.u16()?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        if save_state::crc32(body).to_le_bytes() != checksum {
+            return Err(MovieError::ChecksumMismatch);
+        }
+        let rom_hash = reader.u32()?;
+        let instruction_set = match reader.u8()? {
+            0 => InstructionSet::Chip8,
+            1 => InstructionSet::SuperChip,
+            2 => InstructionSet::XoChip,
+            _ => return Err(MovieError::InvalidData("instruction set")),
+        };
+        let quirks = Quirks::from_bits(reader.u16()?);
+        if reader.u8()? != 0 {
+            return Err(MovieError::InvalidData("random algorithm"));
+        }
+        let seed = reader.u64()?;
+        let timing = match reader.u8()? {
+            0 => Timing::Fixed,
+            1 => Timing::CosmacVip,
+            _ => return Err(MovieError::InvalidData("timing")),
+        };
+        let instructions_per_frame = reader.u32()?;
+        let frames = reader.u64()?;
+        let display_hash = reader.u32()?;
+        let event_count = reader.u32()?;
+        let mut events: Vec<KeyEvent> = Vec::new();
+        for _ in 0..event_count {
+            let frame = reader.u64()?;
+            let byte = reader.u8()?;
+            if byte & 0x70 != 0 || events.last().is_some_and(|last| last.frame > frame) {
+                return Err(MovieError::InvalidData("key event"));
+            }
+            events.push(KeyEvent { frame, key: byte & 0xF, pressed: byte >> 7 == 1 });
+        }
+        Ok(Movie { rom_hash, instruction_set, quirks, seed, timing, instructions_per_frame, events, frames, display_hash })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), MovieError> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, MovieError> {
+        Movie::from_bytes(&fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Waits for a key and shows its digit, over and over
+    const PROGRAM: [u8; 12] = [0xF0, 0x0A, 0x00, 0xE0, 0xF0, 0x29, 0x61, 0x00, 0xD1, 0x15, 0x12, 0x00];
+
+    fn play(emulator: &mut Emulator, frames: u64, mut events: impl FnMut(u64) -> Vec<KeyEvent>) {
+        for frame in 0..frames {
+            for event in events(frame) {
+                emulator.keypad[event.key as usize] = event.pressed;
+            }
+            for _ in 0..10 {
+                emulator.step().unwrap();
+            }
+            emulator.tick_clock();
+        }
+    }
+
+    fn recording() -> (Movie, Emulator) {
+        let mut emulator = Emulator::builder(&PROGRAM).seed(7).build().unwrap();
+        let mut movie = Movie::new(&emulator, 10);
+        let script = [(3, 0x5, true), (5, 0x5, false), (9, 0xA, true), (9, 0x1, true), (12, 0xA, false), (12, 0x1, false)];
+        for (frame, key, pressed) in script {
+            movie.record(frame, key, pressed);
+        }
+        let events = movie.events.clone();
+        play(&mut emulator, 20, |frame| events.iter().filter(|event| event.frame == frame).copied().collect());
+        movie.finish(20, &emulator);
+        (movie, emulator)
+    }
+
+    #[test]
+    fn write_and_read_round_trip() {
+        let (movie, _) = recording();
+        let read = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        assert_eq!(read.rom_hash, movie.rom_hash);
+        assert_eq!(read.instruction_set, movie.instruction_set);
+        assert_eq!(read.quirks, movie.quirks);
+        assert_eq!(read.seed, movie.seed);
+        assert_eq!(read.timing, movie.timing);
+        assert_eq!(read.instructions_per_frame, movie.instructions_per_frame);
+        assert_eq!(read.events, movie.events);
+        assert_eq!(read.frames, movie.frames);
+        assert_eq!(read.display_hash, movie.display_hash);
+        assert_eq!(read.to_bytes(), movie.to_bytes());
+    }
+
+    #[test]
+    fn replay_ends_with_the_recorded_display() {
+        let (movie, recorded) = recording();
+        let mut emulator = movie.emulator(&PROGRAM).unwrap();
+        assert!(!movie.verify(&emulator));
+        play(&mut emulator, movie.frames, |frame| movie.events_at(frame).copied().collect());
+        assert!(movie.verify(&emulator));
+        assert_eq!(emulator.save_state(), recorded.save_state());
+        assert_eq!(movie.events_at(9).count(), 2);
+        assert!(matches!(movie.emulator(&[0x12, 0x00]), Err(MovieError::RomMismatch)));
+    }
+
+    #[test]
+    fn read_rejects_invalid_data() {
+        let (movie, _) = recording();
+        let data = movie.to_bytes();
+
+        let mut corrupted = data.clone();
+        corrupted[12] ^= 0xFF;
+        assert!(matches!(Movie::from_bytes(&corrupted), Err(MovieError::ChecksumMismatch)));
+        assert!(matches!(Movie::from_bytes(&data[..6]), Err(MovieError::Truncated)));
+        assert!(matches!(Movie::from_bytes(b"RCSS\x02\x00\x00\x00\x00\x00"), Err(MovieError::InvalidMagic)));
+
+        // Events must be in order of frame
+        let mut unordered = movie.clone();
+        unordered.events.swap(0, 2);
+        assert!(matches!(Movie::from_bytes(&unordered.to_bytes()), Err(MovieError::InvalidData("key event"))));
+    }
+}
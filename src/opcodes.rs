@@ -0,0 +1,135 @@
+use crate::emulator::InstructionSet;
+use InstructionSet::{Chip8, SuperChip, XoChip};
+use Operand::*;
+
+
+/// An operand of an instruction, either encoded in the opcode or implied by the mnemonic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// Register encoded in the X nibble
+    Vx,
+    /// Register encoded in the Y nibble
+    Vy,
+    /// 8-bit immediate in the low byte
+    Byte,
+    /// 4-bit immediate in the low nibble
+    Nibble,
+    /// 4-bit immediate in the X nibble
+    XNibble,
+    /// 12-bit address in the low three nibbles
+    Address,
+    /// 16-bit address in the word following the opcode
+    LongAddress,
+    /// A fixed keyword, such as `I` or `DT`
+    Keyword(&'static str),
+}
+
+/// An entry of the opcode table. An opcode matches the entry when `opcode & mask == pattern`
+#[derive(Debug, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    pub mask: u16,
+    pub pattern: u16,
+    pub mnemonic: &'static str,
+    pub operands: &'static [Operand],
+    /// The first instruction set that includes this opcode
+    pub instruction_set: InstructionSet,
+}
+
+const fn opcode(mask: u16, pattern: u16, mnemonic: &'static str, operands: &'static [Operand], instruction_set: InstructionSet) -> OpcodeInfo {
+    OpcodeInfo { mask, pattern, mnemonic, operands, instruction_set }
+}
+
+/// Every opcode understood by the emulator, shared by the emulator, the disassembler and the assembler
+pub const OPCODES: &[OpcodeInfo] = &[
+    opcode(0xFFFF, 0x00E0, "CLS", &[], Chip8),
+    opcode(0xFFFF, 0x00EE, "RET", &[], Chip8),
+    opcode(0xFFF0, 0x00C0, "SCD", &[Nibble], SuperChip),
+    opcode(0xFFF0, 0x00D0, "SCU", &[Nibble], XoChip),
+    opcode(0xFFFF, 0x00FB, "SCR", &[], SuperChip),
+    opcode(0xFFFF, 0x00FC, "SCL", &[], SuperChip),
+    opcode(0xFFFF, 0x00FD, "EXIT", &[], SuperChip),
+    opcode(0xFFFF, 0x00FE, "LOW", &[], SuperChip),
+    opcode(0xFFFF, 0x00FF, "HIGH", &[], SuperChip),
+    opcode(0xF000, 0x1000, "JP", &[Address], Chip8),
+    opcode(0xF000, 0x2000, "CALL", &[Address], Chip8),
+    opcode(0xF000, 0x3000, "SE", &[Vx, Byte], Chip8),
+    opcode(0xF000, 0x4000, "SNE", &[Vx, Byte], Chip8),
+    opcode(0xF00F, 0x5000, "SE", &[Vx, Vy], Chip8),
+    opcode(0xF00F, 0x5002, "SAVE", &[Vx, Vy], XoChip),
+    opcode(0xF00F, 0x5003, "LOAD", &[Vx, Vy], XoChip),
+    opcode(0xF000, 0x6000, "LD", &[Vx, Byte], Chip8),
+    opcode(0xF000, 0x7000, "ADD", &[Vx, Byte], Chip8),
+    opcode(0xF00F, 0x8000, "LD", &[Vx, Vy], Chip8),
+    opcode(0xF00F, 0x8001, "OR", &[Vx, Vy], Chip8),
+    opcode(0xF00F, 0x8002, "AND", &[Vx, Vy], Chip8),
+    opcode(0xF00F, 0x8003, "XOR", &[Vx, Vy], Chip8),
+    opcode(0xF00F, 0x8004, "ADD", &[Vx, Vy], Chip8),
+    opcode(0xF00F, 0x8005, "SUB", &[Vx, Vy], Chip8),
+    opcode(0xF00F, 0x8006, "SHR", &[Vx, Vy], Chip8),
+    opcode(0xF00F, 0x8007, "SUBN", &[Vx, Vy], Chip8),
+    opcode(0xF00F, 0x800E, "SHL", &[Vx, Vy], Chip8),
+    opcode(0xF00F, 0x9000, "SNE", &[Vx, Vy], Chip8),
+    opcode(0xF000, 0xA000, "LD", &[Keyword("I"), Address], Chip8),
+    opcode(0xF000, 0xB000, "JP", &[Keyword("V0"), Address], Chip8),
+    opcode(0xF000, 0xC000, "RND", &[Vx, Byte], Chip8),
+    opcode(0xF000, 0xD000, "DRW", &[Vx, Vy, Nibble], Chip8),
+    opcode(0xF0FF, 0xE09E, "SKP", &[Vx], Chip8),
+    opcode(0xF0FF, 0xE0A1, "SKNP", &[Vx], Chip8),
+    opcode(0xFFFF, 0xF000, "LD", &[Keyword("I"), LongAddress], XoChip),
+    opcode(0xF0FF, 0xF001, "PLANE", &[XNibble], XoChip),
+    opcode(0xFFFF, 0xF002, "AUDIO", &[], XoChip),
+    opcode(0xF0FF, 0xF007, "LD", &[Vx, Keyword("DT")], Chip8),
+    opcode(0xF0FF, 0xF00A, "LD", &[Vx, Keyword("K")], Chip8),
+    opcode(0xF0FF, 0xF015, "LD", &[Keyword("DT"), Vx], Chip8),
+    opcode(0xF0FF, 0xF018, "LD", &[Keyword("ST"), Vx], Chip8),
+    opcode(0xF0FF, 0xF01E, "ADD", &[Keyword("I"), Vx], Chip8),
+    opcode(0xF0FF, 0xF029, "LD", &[Keyword("F"), Vx], Chip8),
+    opcode(0xF0FF, 0xF030, "LD", &[Keyword("HF"), Vx], SuperChip),
+    opcode(0xF0FF, 0xF033, "LD", &[Keyword("B"), Vx], Chip8),
+    opcode(0xF0FF, 0xF03A, "PITCH", &[Vx], XoChip),
+    opcode(0xF0FF, 0xF055, "LD", &[Keyword("[I]"), Vx], Chip8),
+    opcode(0xF0FF, 0xF065, "LD", &[Vx, Keyword("[I]")], Chip8),
+    opcode(0xF0FF, 0xF075, "LD", &[Keyword("R"), Vx], SuperChip),
+    opcode(0xF0FF, 0xF085, "LD", &[Vx, Keyword("R")], SuperChip),
+];
+
+impl OpcodeInfo {
+    /// Size of the instruction in bytes, including any following word
+    pub fn size(&self) -> u16 {
+        if self.operands.contains(&LongAddress) { 4 } else { 2 }
+    }
+
+    /// The value of an encoded operand, `next_word` being the word following the opcode
+    pub fn operand_value(operand: Operand, opcode: u16, next_word: u16) -> u16 {
+        match operand {
+            Vx | XNibble => (opcode & 0x0F00) >> 8,
+            Vy => (opcode & 0x00F0) >> 4,
+            Byte => opcode & 0x00FF,
+            Nibble => opcode & 0x000F,
+            Address => opcode & 0x0FFF,
+            LongAddress => next_word,
+            Keyword(_) => 0,
+        }
+    }
+
+    /// Encodes an operand value into the opcode, returning `None` if it doesn't fit
+    pub fn encode_operand(operand: Operand, opcode: u16, value: u16) -> Option<u16> {
+        let (max, shift) = match operand {
+            Vx | XNibble => (0xF, 8),
+            Vy => (0xF, 4),
+            Byte => (0xFF, 0),
+            Nibble => (0xF, 0),
+            Address => (0xFFF, 0),
+            LongAddress | Keyword(_) => return Some(opcode),
+        };
+        if value > max {
+            return None;
+        }
+        Some(opcode | value << shift)
+    }
+}
+
+/// Finds the table entry for an opcode, if the instruction set includes it
+pub fn decode(opcode: u16, instruction_set: InstructionSet) -> Option<&'static OpcodeInfo> {
+    OPCODES.iter().find(|info| opcode & info.mask == info.pattern && info.instruction_set <= instruction_set)
+}
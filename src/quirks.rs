@@ -0,0 +1,189 @@
+use crate::emulator::InstructionSet;
+
+
+/// Behaviours that differ between CHIP-8 implementations. Roms written for one implementation
+/// may rely on any of these, so they can be toggled individually or set from a [`Platform`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6 and 8XYE set VX to VY before shifting instead of shifting VX in place
+    pub shift_sets_vx: bool,
+    /// BNNN jumps to NNN + VX instead of NNN + V0, as in CHIP-48 and SUPER-CHIP
+    pub jump_with_offset_bug_emulation: bool,
+    /// FX55 and FX65 leave I pointing after the last register stored or loaded
+    pub increment_i_on_store_and_load: bool,
+    /// 8XY1, 8XY2 and 8XY3 reset VF to 0
+    pub vf_reset: bool,
+    /// DXYN waits for the next vertical blank before drawing, limiting draws to one per frame
+    pub display_wait: bool,
+    /// Sprites are clipped at the edges of the screen instead of wrapping around
+    pub clip_sprites: bool,
+    /// FX1E sets VF when I overflows past the end of memory
+    pub index_overflow_sets_vf: bool,
+    /// FX0A only completes once the pressed key is released, as on the COSMAC VIP
+    pub key_wait_on_release: bool,
+}
+
+/// Known CHIP-8 implementations, each with its own instruction set and quirks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    CosmacVip,
+    Chip48,
+    SuperChip10,
+    SuperChip11,
+    Modern,
+    XoChip,
+}
+
+impl Platform {
+    pub fn instruction_set(&self) -> InstructionSet {
+        match self {
+            Platform::CosmacVip | Platform::Chip48 | Platform::Modern => InstructionSet::Chip8,
+            Platform::SuperChip10 | Platform::SuperChip11 => InstructionSet::SuperChip,
+            Platform::XoChip => InstructionSet::XoChip,
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::CosmacVip => Quirks {
+                shift_sets_vx: true,
+                jump_with_offset_bug_emulation: false,
+                increment_i_on_store_and_load: true,
+                vf_reset: true,
+                display_wait: true,
+                clip_sprites: true,
+                index_overflow_sets_vf: false,
+                key_wait_on_release: true,
+            },
+            Platform::Chip48 => Quirks {
+                shift_sets_vx: false,
+                jump_with_offset_bug_emulation: true,
+                increment_i_on_store_and_load: true,
+                vf_reset: false,
+                display_wait: false,
+                clip_sprites: true,
+                index_overflow_sets_vf: false,
+                key_wait_on_release: false,
+            },
+            Platform::SuperChip10 => Quirks {
+                shift_sets_vx: false,
+                jump_with_offset_bug_emulation: true,
+                increment_i_on_store_and_load: true,
+                vf_reset: false,
+                display_wait: false,
+                clip_sprites: true,
+                index_overflow_sets_vf: false,
+                key_wait_on_release: false,
+            },
+            Platform::SuperChip11 => Quirks {
+                shift_sets_vx: false,
+                jump_with_offset_bug_emulation: true,
+                increment_i_on_store_and_load: false,
+                vf_reset: false,
+                display_wait: false,
+                clip_sprites: true,
+                index_overflow_sets_vf: false,
+                key_wait_on_release: false,
+            },
+            Platform::Modern => Quirks {
+                shift_sets_vx: false,
+                jump_with_offset_bug_emulation: false,
+                increment_i_on_store_and_load: false,
+                vf_reset: false,
+                display_wait: false,
+                clip_sprites: true,
+                // Original cosmac interpreter does not check for overflow however some interpreters do
+                // and at least one game is known to rely on this. No known games relies on this not happening
+                index_overflow_sets_vf: true,
+                key_wait_on_release: false,
+            },
+            Platform::XoChip => Quirks {
+                shift_sets_vx: true,
+                jump_with_offset_bug_emulation: false,
+                increment_i_on_store_and_load: true,
+                vf_reset: false,
+                display_wait: false,
+                clip_sprites: false,
+                index_overflow_sets_vf: false,
+                key_wait_on_release: false,
+            },
+        }
+    }
+}
+
+impl Quirks {
+    // Packs the quirks into a bitfield for save states
+    pub(crate) fn to_bits(self) -> u16 {
+        [
+            self.shift_sets_vx,
+            self.jump_with_offset_bug_emulation,
+            self.increment_i_on_store_and_load,
+            self.vf_reset,
+            self.display_wait,
+            self.clip_sprites,
+            self.index_overflow_sets_vf,
+            self.key_wait_on_release,
+        ].iter().enumerate().fold(0, |bits, (i, quirk)| bits | (*quirk as u16) << i)
+    }
+
+    pub(crate) fn from_bits(bits: u16) -> Quirks {
+        let bit = |i: u16| bits >> i & 1 == 1;
+        Quirks {
+            shift_sets_vx: bit(0),
+            jump_with_offset_bug_emulation: bit(1),
+            increment_i_on_store_and_load: bit(2),
+            vf_reset: bit(3),
+            display_wait: bit(4),
+            clip_sprites: bit(5),
+            index_overflow_sets_vf: bit(6),
+            key_wait_on_release: bit(7),
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Platform::Modern.quirks()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The quirks of every platform, in the order vF reset, memory, display wait, clipping, shifting VY and jumping with
+    // VX. The COSMAC VIP, SUPER-CHIP 1.1 and XO-CHIP rows are the results Timendus' quirks test expects of CHIP-8,
+    // SUPER-CHIP and XO-CHIP, the others follow CHIP-48 and SUPER-CHIP 1.0
+    const REFERENCE: [(Platform, [bool; 6]); 6] = [
+        (Platform::CosmacVip, [true, true, true, true, true, false]),
+        (Platform::Chip48, [false, true, false, true, false, true]),
+        (Platform::SuperChip10, [false, true, false, true, false, true]),
+        (Platform::SuperChip11, [false, false, false, true, false, true]),
+        (Platform::Modern, [false, false, false, true, false, false]),
+        (Platform::XoChip, [false, true, false, false, true, false]),
+    ];
+
+    #[test]
+    fn platforms_match_reference() {
+        for (platform, expected) in REFERENCE {
+            let quirks = platform.quirks();
+            let actual = [
+                quirks.vf_reset,
+                quirks.increment_i_on_store_and_load,
+                quirks.display_wait,
+                quirks.clip_sprites,
+                quirks.shift_sets_vx,
+                quirks.jump_with_offset_bug_emulation,
+            ];
+            assert_eq!(actual, expected, "{:?}", platform);
+        }
+    }
+
+    #[test]
+    fn bits_round_trip() {
+        for (platform, _) in REFERENCE {
+            assert_eq!(Quirks::from_bits(platform.quirks().to_bits()), platform.quirks());
+        }
+    }
+}
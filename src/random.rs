@@ -0,0 +1,60 @@
+use crate::save_state::{SaveStateError, StateReader, StateWriter};
+
+
+/// The xorshift random number generator used by the CXNN instruction. Runs with the same seed produce the same numbers,
+/// and the state is kept in save states
+#[derive(Debug, Clone)]
+pub struct Random {
+    seed: u64,
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        // Xorshift gets stuck at 0, so the seed is scrambled into a state that never is
+        Random { seed, state: splitmix64(seed) | 1 }
+    }
+
+    /// A generator with a seed taken from the system's entropy source
+    pub fn from_entropy() -> Random {
+        Random::new(rand::random())
+    }
+
+    /// The seed the generator was created with
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub(crate) fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545F4914F6CDD1D) >> 56) as u8
+    }
+
+    pub(crate) fn write(&self, writer: &mut StateWriter) {
+        // The algorithm, of which xorshift is the only one
+        writer.u8(0);
+        writer.u64(self.seed);
+        writer.u64(self.state);
+    }
+
+    pub(crate) fn read(reader: &mut StateReader) -> Result<Random, SaveStateError> {
+        if reader.u8()? != 0 {
+            return Err(SaveStateError::InvalidData("random algorithm"));
+        }
+        let seed = reader.u64()?;
+        let state = reader.u64()?;
+        if state == 0 {
+            return Err(SaveStateError::InvalidData("random state"));
+        }
+        Ok(Random { seed, state })
+    }
+}
+
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,194 @@
+use std::collections::VecDeque;
+
+
+/// A bounded history of save states for stepping backwards in time.
+///
+/// Only the newest state is kept in full. Every older state is stored as the difference between it and
+/// the state that followed it, run length encoded, which is usually a few bytes since little changes
+/// between frames. Rewinding applies the newest difference to the newest state to recover the one before it.
+pub struct Rewind {
+    current: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    max_snapshots: usize,
+    memory_budget: usize,
+    used_memory: usize,
+}
+
+impl Rewind {
+    /// Creates a history holding at most `max_snapshots` states and `memory_budget` bytes of differences
+    pub fn new(max_snapshots: usize, memory_budget: usize) -> Rewind {
+        Rewind {
+            current: None,
+            deltas: VecDeque::new(),
+            max_snapshots,
+            memory_budget,
+            used_memory: 0,
+        }
+    }
+
+    /// Number of states that can currently be rewound to
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.current.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.current = None;
+        self.deltas.clear();
+        self.used_memory = 0;
+    }
+
+    /// Records a new state as the newest one in the history, evicting the oldest states past the limits
+    pub fn capture(&mut self, state: Vec<u8>) {
+        match self.current.take() {
+            Some(previous) if previous.len() == state.len() => {
+                let delta = encode_delta(&previous, &state);
+                self.used_memory += delta.len();
+                self.deltas.push_back(delta);
+            }
+            // States of different sizes cannot be diffed, so the history restarts
+            Some(_) => self.clear(),
+            None => {}
+        }
+        self.current = Some(state);
+
+        while self.len() > self.max_snapshots.max(1) || self.used_memory > self.memory_budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.used_memory -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Removes the newest state from the history and returns it, so that calling this repeatedly steps backwards
+    pub fn step_back(&mut self) -> Option<Vec<u8>> {
+        let state = self.current.take()?;
+        if let Some(delta) = self.deltas.pop_back() {
+            self.used_memory -= delta.len();
+            let mut previous = state.clone();
+            apply_delta(&mut previous, &delta);
+            self.current = Some(previous);
+        }
+        Some(state)
+    }
+}
+
+// The delta is a sequence of (unchanged byte count, changed byte count, changed bytes xor-ed with the old ones)
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut position = 0;
+    while position < old.len() {
+        let unchanged = old[position..].iter().zip(&new[position..]).take_while(|(a, b)| a == b).count();
+        position += unchanged;
+        let changed = old[position..].iter().zip(&new[position..]).take_while(|(a, b)| a != b).count();
+        write_varint(&mut delta, unchanged);
+        write_varint(&mut delta, changed);
+        delta.extend(old[position..position + changed].iter().zip(&new[position..]).map(|(a, b)| a ^ b));
+        position += changed;
+    }
+    delta
+}
+
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut position = 0;
+    let mut cursor = 0;
+    while cursor < delta.len() {
+        position += read_varint(delta, &mut cursor);
+        let changed = read_varint(delta, &mut cursor);
+        for (byte, xor) in state[position..position + changed].iter_mut().zip(&delta[cursor..cursor + changed]) {
+            *byte ^= xor;
+        }
+        position += changed;
+        cursor += changed;
+    }
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn read_varint(buffer: &[u8], cursor: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buffer[*cursor];
+        *cursor += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A state of the given size where every byte at a multiple of `every` is changed by `frame`
+    fn state(frame: u8, size: usize, every: usize) -> Vec<u8> {
+        (0..size).map(|i| if i % every == 0 { (i as u8).wrapping_add(frame) } else { i as u8 }).collect()
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let old = state(0, 1000, 3);
+        let cases = [
+            old.clone(),
+            state(1, 1000, 3),
+            state(1, 1000, 300),
+            old.iter().map(|byte| !byte).collect(),
+            old.iter().enumerate().map(|(i, byte)| if (200..600).contains(&i) { byte ^ 0x5A } else { *byte }).collect(),
+        ];
+        for new in cases {
+            let delta = encode_delta(&old, &new);
+            let mut decoded = old.clone();
+            apply_delta(&mut decoded, &delta);
+            assert_eq!(decoded, new);
+        }
+    }
+
+    #[test]
+    fn delta_of_unchanged_state_is_small() {
+        let old = state(0, 5000, 7);
+        assert!(encode_delta(&old, &old).len() <= 4);
+        assert!(encode_delta(&old, &state(1, 5000, 1000)).len() < 40);
+    }
+
+    #[test]
+    fn steps_back_through_captured_states() {
+        let mut rewind = Rewind::new(10, 1 << 20);
+        for frame in 0..5 {
+            rewind.capture(state(frame, 100, 10));
+        }
+        assert_eq!(rewind.len(), 5);
+        for frame in (0..5).rev() {
+            assert_eq!(rewind.step_back(), Some(state(frame, 100, 10)));
+        }
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.step_back(), None);
+    }
+
+    #[test]
+    fn evicts_the_oldest_states() {
+        let mut rewind = Rewind::new(3, 1 << 20);
+        for frame in 0..6 {
+            rewind.capture(state(frame, 100, 10));
+        }
+        assert_eq!(rewind.len(), 3);
+        let states: Vec<Vec<u8>> = std::iter::from_fn(|| rewind.step_back()).collect();
+        assert_eq!(states, vec![state(5, 100, 10), state(4, 100, 10), state(3, 100, 10)]);
+
+        // States of another size restart the history
+        rewind.capture(state(0, 100, 10));
+        rewind.capture(state(0, 50, 10));
+        assert_eq!(rewind.len(), 1);
+    }
+}
@@ -0,0 +1,255 @@
+use thiserror::Error;
+
+
+// Save states start with this magic, followed by the format version, the hash of the rom they were
+// saved from and the length of the payload. The payload is followed by a checksum of everything before it.
+const MAGIC: [u8; 4] = *b"RCSS";
+pub const VERSION: u16 = 4;
+const HEADER_SIZE: usize = 14;
+
+
+#[derive(Error, Debug)]
+pub enum SaveStateError {
+    #[error("The data is not a rustychip save state")]
+    InvalidMagic,
+    #[error("Save state version {0} is not supported, expected version {VERSION}")]
+    UnsupportedVersion(u16),
+    #[error("The save state is corrupted, its checksum does not match")]
+    ChecksumMismatch,
+    #[error("The save state was made with a different rom")]
+    RomMismatch,
+    #[error("The save state ended unexpectedly")]
+    Truncated,
+    #[error("The save state contains an invalid value: {0}")]
+    InvalidData(&'static str),
+}
+
+/// CRC-32 (IEEE) of the given data, used both to identify roms and to checksum save states
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Wraps a payload with the save state header and checksum
+pub(crate) fn encode(rom_hash: u32, payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER_SIZE + payload.len() + 4);
+    data.extend_from_slice(&MAGIC);
+    data.extend_from_slice(&VERSION.to_le_bytes());
+    data.extend_from_slice(&rom_hash.to_le_bytes());
+    data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    data.extend_from_slice(payload);
+    let checksum = crc32(&data);
+    data.extend_from_slice(&checksum.to_le_bytes());
+    data
+}
+
+/// Validates the header and checksum of a save state and returns its payload
+pub(crate) fn decode(data: &[u8], rom_hash: u32) -> Result<&[u8], SaveStateError> {
+    let mut reader = StateReader::new(data);
+    if reader.bytes(MAGIC.len())? != MAGIC {
+        return Err(SaveStateError::InvalidMagic);
+    }
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(SaveStateError::UnsupportedVersion(version));
+    }
+    let state_rom_hash = reader.u32()?;
+    let length = reader.u32()? as usize;
+    let payload = reader.bytes(length)?;
+    let checksum = reader.u32()?;
+    if checksum != crc32(&data[..HEADER_SIZE + length]) {
+        return Err(SaveStateError::ChecksumMismatch);
+    }
+    if state_rom_hash != rom_hash {
+        return Err(SaveStateError::RomMismatch);
+    }
+    Ok(payload)
+}
+
+#[derive(Default)]
+pub(crate) struct StateWriter {
+    buffer: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter::default()
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.buffer.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.buffer.extend_from_slice(value);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader {
+            data,
+            position: 0,
+        }
+    }
+
+    pub fn bytes(&mut self, length: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.data.len() - self.position < length {
+            return Err(SaveStateError::Truncated);
+        }
+        let bytes = &self.data[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::InvalidData("boolean")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{Emulator, InstructionSet};
+
+    // Counts up in V0, stores its digits with FX33, draws the first one, calls a subroutine and loops
+    const PROGRAM: [u8; 16] = [
+        0x70, 0x01, 0xA3, 0x00, 0xF0, 0x33, 0xF0, 0x29, 0xD1, 0x25, 0x22, 0x0E, 0x12, 0x00, 0x00, 0xEE,
+    ];
+
+    fn emulator(program: &[u8]) -> Emulator {
+        Emulator::builder(program).seed(1).build().unwrap()
+    }
+
+    fn run(emulator: &mut Emulator, frames: usize) {
+        for _ in 0..frames {
+            for _ in 0..10 {
+                emulator.step().unwrap();
+            }
+            emulator.tick_clock();
+        }
+    }
+
+    // Replaces the payload of a save state, keeping the header and checksum valid
+    fn with_payload(state: &[u8], rom_hash: u32, change: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+        let mut payload = decode(state, rom_hash).unwrap().to_vec();
+        change(&mut payload);
+        encode(rom_hash, &payload)
+    }
+
+    #[test]
+    fn crc32_matches_the_ieee_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut original = emulator(&PROGRAM);
+        original.delay_timer = 30;
+        run(&mut original, 5);
+        let state = original.save_state();
+
+        let mut restored = emulator(&PROGRAM);
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.pc(), original.pc());
+        assert_eq!(restored.registers(), original.registers());
+
+        // Both carry on the same way
+        run(&mut original, 5);
+        run(&mut restored, 5);
+        assert_eq!(restored.save_state(), original.save_state());
+    }
+
+    #[test]
+    fn load_rejects_invalid_headers() {
+        let mut emulator = emulator(&PROGRAM);
+        let state = emulator.save_state();
+
+        let mut corrupted = state.clone();
+        corrupted[HEADER_SIZE + 10] ^= 0xFF;
+        assert!(matches!(emulator.load_state(&corrupted), Err(SaveStateError::ChecksumMismatch)));
+        assert!(matches!(emulator.load_state(&state[..state.len() - 1]), Err(SaveStateError::Truncated)));
+        assert!(matches!(emulator.load_state(b"not a save state"), Err(SaveStateError::InvalidMagic)));
+
+        let mut old = state.clone();
+        old[4..6].copy_from_slice(&(VERSION - 1).to_le_bytes());
+        assert!(matches!(emulator.load_state(&old), Err(SaveStateError::UnsupportedVersion(_))));
+
+        let mut other = Emulator::builder(&[0x12, 0x00]).build().unwrap();
+        assert!(matches!(other.load_state(&state), Err(SaveStateError::RomMismatch)));
+    }
+
+    #[test]
+    fn load_rejects_out_of_range_values() {
+        let mut emulator = Emulator::builder(&PROGRAM).instruction_set(InstructionSet::XoChip).build().unwrap();
+        let state = emulator.save_state();
+        let rom_hash = emulator.rom_hash();
+        let memory_size = emulator.memory_size();
+
+        // The bitplanes follow the instruction set, quirks, memory size, memory and resolution
+        let planes = with_payload(&state, rom_hash, |payload| payload[1 + 2 + 4 + memory_size + 1] = 4);
+        assert!(matches!(emulator.load_state(&planes), Err(SaveStateError::InvalidData("bitplanes"))));
+
+        // The font address is followed by the random generator, the timing model and the cycle counters
+        let font_address = with_payload(&state, rom_hash, |payload| {
+            let offset = payload.len() - (17 + 1 + 8 + 4) - 2;
+            payload[offset..offset + 2].copy_from_slice(&0xFFF0u16.to_le_bytes());
+        });
+        assert!(matches!(emulator.load_state(&font_address), Err(SaveStateError::InvalidData("font address"))));
+
+        // A rejected state leaves the emulator as it was
+        assert_eq!(emulator.save_state(), state);
+    }
+}
@@ -0,0 +1,118 @@
+use std::mem;
+use std::time::{Duration, Instant};
+
+
+/// Emulated frames per second. The timers tick once per frame
+pub const FRAME_RATE: u32 = 60;
+
+pub const MIN_SPEED: f64 = 0.125;
+pub const MAX_SPEED: f64 = 16.0;
+
+// The most frames run at once at normal speed to catch up after the host stalls. Anything beyond is dropped rather
+// than run in a burst
+const MAX_CATCH_UP_FRAMES: f64 = 4.0;
+
+/// Decides when emulated frames run, so that they run at 60 per second of real time multiplied by the speed, whatever
+/// the speed of the host. Slow motion and fast forward are speeds below and above 1. While paused no frames run,
+/// except those requested one at a time with [`Scheduler::advance_frame`].
+pub struct Scheduler {
+    speed: f64,
+    fast_forward_speed: f64,
+    fast_forwarding: bool,
+    paused: bool,
+    frames_to_advance: u32,
+    // Emulated frames owed since the last frame ran, less than one unless frames are due
+    owed_frames: f64,
+    last_update: Option<Instant>,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler::new()
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler {
+            speed: 1.0,
+            fast_forward_speed: 4.0,
+            fast_forwarding: false,
+            paused: false,
+            frames_to_advance: 0,
+            owed_frames: 0.0,
+            last_update: None,
+        }
+    }
+
+    /// The speed while not fast forwarding, where 1 is real time
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Sets the speed while not fast forwarding, limited to [`MIN_SPEED`] to [`MAX_SPEED`]
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    /// Sets the speed while fast forwarding, limited to [`MIN_SPEED`] to [`MAX_SPEED`]. Defaults to 4
+    pub fn set_fast_forward_speed(&mut self, speed: f64) {
+        self.fast_forward_speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    pub fn set_fast_forwarding(&mut self, fast_forwarding: bool) {
+        self.fast_forwarding = fast_forwarding;
+    }
+
+    pub fn is_fast_forwarding(&self) -> bool {
+        self.fast_forwarding
+    }
+
+    /// The speed frames currently run at
+    pub fn current_speed(&self) -> f64 {
+        if self.fast_forwarding { self.fast_forward_speed } else { self.speed }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.frames_to_advance = 0;
+    }
+
+    /// Pauses, then runs a single frame the next time frames are due
+    pub fn advance_frame(&mut self) {
+        self.paused = true;
+        self.frames_to_advance += 1;
+    }
+
+    /// The number of frames to run now, given the real time that passed since this was last called
+    pub fn frames_due(&mut self, now: Instant) -> u32 {
+        let elapsed = self.last_update.map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+        self.last_update = Some(now);
+        if self.paused {
+            self.owed_frames = 0.0;
+            return mem::take(&mut self.frames_to_advance);
+        }
+        let speed = self.current_speed();
+        self.owed_frames += elapsed.as_secs_f64() * FRAME_RATE as f64 * speed;
+        let frames = self.owed_frames.floor().min((MAX_CATCH_UP_FRAMES * speed).ceil());
+        // Only the part of a frame is kept, any frames beyond the catch up limit are dropped
+        self.owed_frames = self.owed_frames.fract();
+        frames as u32
+    }
+
+    /// How long until the next frame is due, at most one frame at normal speed so that input is still handled
+    /// regularly while paused or in slow motion
+    pub fn time_to_next_frame(&self, now: Instant) -> Duration {
+        let frame = Duration::from_secs_f64(1.0 / FRAME_RATE as f64);
+        let last_update = match self.last_update {
+            Some(last_update) if !self.paused => last_update,
+            _ => return frame,
+        };
+        let due = last_update + frame.mul_f64((1.0 - self.owed_frames) / self.current_speed());
+        due.saturating_duration_since(now).min(frame)
+    }
+}
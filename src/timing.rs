@@ -0,0 +1,148 @@
+/// How long instructions take to run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    /// Every instruction takes the same time, and the frontend decides how many run each frame
+    Fixed,
+    /// Each instruction takes as many machine cycles as the routine of the COSMAC VIP interpreter that ran it, and
+    /// every frame only has the cycles left over by the vertical blank interrupt and the display DMA
+    CosmacVip,
+}
+
+/// Machine cycles of the COSMAC VIP's 1802 in one 60Hz frame, at 1.76 MHz and 8 clock cycles per machine cycle
+pub const CYCLES_PER_FRAME: u32 = 3668;
+
+/// Machine cycles of each frame taken by the interrupt routine, which ticks the timers, and the DMA that feeds the
+/// display from memory
+pub const INTERRUPT_CYCLES: u32 = 1832;
+
+// Every instruction is fetched and decoded by the same code before its routine runs
+const FETCH_CYCLES: u32 = 68;
+
+// Conditional skips cost more when they skip
+const SKIP_CYCLES: u32 = 4;
+
+/// The machine cycles a CHIP-8 instruction takes on the COSMAC VIP, including fetching and decoding it, given the
+/// registers and index before it runs and whether it skipped the next instruction. Drawing costs more for every row
+/// and for sprites that aren't aligned to a byte, as the interpreter shifts each row into place bit by bit, and
+/// storing a number as BCD costs more the larger its digits are, as it counts them by repeated subtraction.
+///
+/// Instructions the VIP doesn't have are charged as an average instruction
+pub fn instruction_cycles(opcode: u16, registers: &[u8; 16], index: u16, skipped: bool) -> u32 {
+    let x = (opcode >> 8 & 0xF) as usize;
+    let nn = opcode & 0xFF;
+    let n = (opcode & 0xF) as u32;
+    let vx = registers[x];
+    let cycles = match opcode >> 12 {
+        0x0 if opcode == 0x00E0 => 3078,
+        0x0 if opcode == 0x00EE => 10,
+        0x1 => 12,
+        0x2 => 26,
+        0x3 | 0x4 => 10,
+        0x5 | 0x9 => 14,
+        0x6 => 6,
+        0x7 => 10,
+        0x8 if opcode & 0xF == 0 => 12,
+        0x8 => 44,
+        0xA => 12,
+        // Crossing a page takes an extra carry
+        0xB if (opcode & 0xFF) as u32 + registers[0] as u32 > 0xFF => 24,
+        0xB => 22,
+        0xC => 36,
+        0xD => 68 + n * (46 + 8 * (vx as u32 & 7)),
+        0xE => 14,
+        0xF => match nn {
+            0x07 | 0x15 | 0x18 => 10,
+            0x0A => 18,
+            0x1E if (index & 0xFF) + vx as u16 > 0xFF => 22,
+            0x1E => 16,
+            0x29 => 16,
+            0x33 => 80 + 16 * (vx as u32 / 100 + vx as u32 / 10 % 10 + vx as u32 % 10),
+            0x55 | 0x65 => 14 + 14 * (x as u32 + 1),
+            _ => 20,
+        },
+        _ => 20,
+    };
+    FETCH_CYCLES + cycles + if skipped { SKIP_CYCLES } else { 0 }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+
+    fn cycles(opcode: u16, x: u8, index: u16) -> u32 {
+        let mut registers = [0; 16];
+        registers[(opcode >> 8 & 0xF) as usize] = x;
+        instruction_cycles(opcode, &registers, index, false)
+    }
+
+    #[test]
+    fn fixed_costs() {
+        let costs = [
+            (0x00E0, 3146), (0x00EE, 78), (0x1200, 80), (0x2200, 94), (0x3000, 78), (0x5010, 82), (0x6000, 74),
+            (0x7001, 78), (0x8010, 80), (0x8014, 112), (0xA200, 80), (0xC0FF, 104), (0xE09E, 82), (0xF007, 78),
+            (0xF00A, 86), (0xF029, 84), (0xF055, 96), (0xF355, 138), (0xF065, 96), (0x00FF, 88),
+        ];
+        for (opcode, expected) in costs {
+            assert_eq!(cycles(opcode, 0, 0), expected, "{:04x}", opcode);
+        }
+        assert_eq!(instruction_cycles(0x3000, &[0; 16], 0, true), 82);
+    }
+
+    #[test]
+    fn drawing_costs_rows_and_shifts() {
+        // 68 to set up, then 46 per row and 8 more per bit each row is shifted by
+        assert_eq!(cycles(0xD001, 0, 0), 68 + 68 + 46);
+        assert_eq!(cycles(0xD00F, 0, 0), 68 + 68 + 15 * 46);
+        assert_eq!(cycles(0xD005, 3, 0), 68 + 68 + 5 * (46 + 24));
+        assert_eq!(cycles(0xD005, 7, 0), 68 + 68 + 5 * (46 + 56));
+        assert_eq!(cycles(0xD005, 8, 0), cycles(0xD005, 0, 0));
+        assert_eq!(cycles(0xD000, 5, 0), 68 + 68);
+    }
+
+    #[test]
+    fn bcd_costs_digits() {
+        // 80, then 16 for every subtraction counting the digits
+        assert_eq!(cycles(0xF033, 0, 0), 68 + 80);
+        assert_eq!(cycles(0xF033, 9, 0), 68 + 80 + 16 * 9);
+        assert_eq!(cycles(0xF033, 100, 0), 68 + 80 + 16);
+        assert_eq!(cycles(0xF033, 255, 0), 68 + 80 + 16 * 12);
+    }
+
+    #[test]
+    fn page_crossing_costs_carry() {
+        let mut registers = [0; 16];
+        registers[0] = 0x10;
+        assert_eq!(instruction_cycles(0xB2EF, &registers, 0, false), 68 + 22);
+        assert_eq!(instruction_cycles(0xB2F0, &registers, 0, false), 68 + 24);
+        assert_eq!(cycles(0xF01E, 0x10, 0x2EF), 68 + 16);
+        assert_eq!(cycles(0xF01E, 0x10, 0x2F0), 68 + 22);
+    }
+
+    #[test]
+    fn frame_budget() {
+        assert_eq!(CYCLES_PER_FRAME - INTERRUPT_CYCLES, 1836);
+
+        // 6000 takes 74 cycles, so 25 fit in the 1836 a frame leaves, with the last one running into the next frame
+        let mut emulator = Emulator::builder(&[0x60, 0x00].repeat(100)).timing(Timing::CosmacVip).build().unwrap();
+        let mut instructions = 0;
+        while !emulator.frame_complete(instructions, 1) {
+            emulator.step().unwrap();
+            instructions += 1;
+        }
+        assert_eq!(instructions, 25);
+        assert_eq!(emulator.cycles(), 25 * 74);
+        emulator.tick_clock();
+        assert_eq!(emulator.cycles(), 25 * 74 + INTERRUPT_CYCLES as u64);
+
+        // The 14 cycles run past the end of the frame are taken from the next one
+        let mut instructions = 0;
+        while !emulator.frame_complete(instructions, 1) {
+            emulator.step().unwrap();
+            instructions += 1;
+        }
+        assert_eq!(instructions, 25);
+        assert_eq!(emulator.cycles(), 50 * 74 + INTERRUPT_CYCLES as u64);
+    }
+}
@@ -0,0 +1,581 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+use crate::disassembler::DecodedInstruction;
+use crate::emulator::{Emulator, InstructionSet};
+use crate::opcodes;
+
+
+// Binary traces start with this magic and the format version, followed by the instruction set
+const MAGIC: [u8; 4] = *b"RCTR";
+pub const TRACE_VERSION: u16 = 1;
+
+// Each entry of a binary trace starts with these flags, saying which of its fields are stored. Fields that aren't
+// follow from the previous entry
+const STORES_STEP: u8 = 1 << 0;
+const STORES_PC: u8 = 1 << 1;
+const STORES_NEXT_WORD: u8 = 1 << 2;
+const STORES_INDEX: u8 = 1 << 3;
+const STORES_REGISTERS: u8 = 1 << 4;
+
+#[derive(Error, Debug)]
+pub enum TraceError {
+    #[error("The data is not a rustychip trace")]
+    InvalidMagic,
+    #[error("Trace version {0} is not supported, expected version {TRACE_VERSION}")]
+    UnsupportedVersion(u16),
+    #[error("The trace ended in the middle of an instruction")]
+    Truncated,
+    #[error("The trace contains an invalid value: {0}")]
+    InvalidData(&'static str),
+    #[error("Error accessing the trace file: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// A group of related instructions, to only trace some of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpcodeClass {
+    /// Jumps, calls, returns, skips and exiting
+    Flow,
+    /// Loading, arithmetic and logic on registers, and random numbers
+    Arithmetic,
+    /// Setting the index register and loading or storing memory through it
+    Memory,
+    /// Clearing, scrolling, drawing, switching resolution and selecting planes
+    Display,
+    /// Skipping on keys and waiting for a key
+    Input,
+    /// Reading and setting the timers, and the XO-CHIP audio pattern and pitch
+    Timer,
+}
+
+impl OpcodeClass {
+    pub fn of(opcode: u16) -> OpcodeClass {
+        match opcode >> 12 {
+            0x0 if matches!(opcode, 0x00E0 | 0x00FB | 0x00FC | 0x00FE | 0x00FF) || opcode & 0xFFE0 == 0x00C0 => OpcodeClass::Display,
+            0x0 | 0x1 | 0x2 | 0x3 | 0x4 | 0x9 | 0xB => OpcodeClass::Flow,
+            0x5 if opcode & 0xF == 0 => OpcodeClass::Flow,
+            0x5 | 0xA => OpcodeClass::Memory,
+            0x6 | 0x7 | 0x8 | 0xC => OpcodeClass::Arithmetic,
+            0xD => OpcodeClass::Display,
+            0xE => OpcodeClass::Input,
+            _ => match opcode & 0xFF {
+                0x01 => OpcodeClass::Display,
+                0x0A => OpcodeClass::Input,
+                0x02 | 0x07 | 0x15 | 0x18 | 0x3A => OpcodeClass::Timer,
+                _ => OpcodeClass::Memory,
+            },
+        }
+    }
+}
+
+impl FromStr for OpcodeClass {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_ascii_lowercase().as_str() {
+            "flow" => Ok(OpcodeClass::Flow),
+            "arithmetic" => Ok(OpcodeClass::Arithmetic),
+            "memory" => Ok(OpcodeClass::Memory),
+            "display" => Ok(OpcodeClass::Display),
+            "input" => Ok(OpcodeClass::Input),
+            "timer" => Ok(OpcodeClass::Timer),
+            _ => Err(format!("Unknown opcode class `{}`, expected flow, arithmetic, memory, display, input or timer", text)),
+        }
+    }
+}
+
+/// Which instructions a [`Tracer`] writes. By default every instruction is traced, while address ranges and opcode
+/// classes limit the trace to instructions at any of the ranges and of any of the classes
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    ranges: Vec<RangeInclusive<u16>>,
+    classes: Vec<OpcodeClass>,
+    limit: Option<u64>,
+}
+
+impl TraceFilter {
+    pub fn new() -> TraceFilter {
+        TraceFilter::default()
+    }
+
+    /// Traces instructions at addresses in the range
+    pub fn with_range(mut self, range: RangeInclusive<u16>) -> TraceFilter {
+        self.ranges.push(range);
+        self
+    }
+
+    /// Traces instructions of the class
+    pub fn with_class(mut self, class: OpcodeClass) -> TraceFilter {
+        self.classes.push(class);
+        self
+    }
+
+    /// Stops tracing once the given number of instructions passed the other filters
+    pub fn with_limit(mut self, limit: u64) -> TraceFilter {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn matches(&self, pc: u16, opcode: u16) -> bool {
+        (self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&pc)))
+            && (self.classes.is_empty() || self.classes.contains(&OpcodeClass::of(opcode)))
+    }
+}
+
+/// An instruction that ran, with the registers it changed and the index register after it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// The number of instructions that ran before this one
+    pub step: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    /// The word following the opcode for 4 byte instructions, 0 for others
+    pub next_word: u16,
+    /// The index register after the instruction
+    pub index: u16,
+    /// The registers the instruction changed, as the register number and its new value
+    pub registers: Vec<(u8, u8)>,
+}
+
+impl TraceEntry {
+    /// The instruction in assembly, or `???` if the instruction set doesn't have it
+    pub fn mnemonic(&self, instruction_set: InstructionSet) -> String {
+        match opcodes::decode(self.opcode, instruction_set) {
+            Some(info) => DecodedInstruction { address: self.pc, opcode: self.opcode, next_word: self.next_word, info }.format(|_| None),
+            None => "???".to_string(),
+        }
+    }
+
+    /// The entry as a line of text, such as `      1042  0228  7001  ADD V0, 0x01          I=0275  V0=0c VF=00`
+    pub fn format(&self, instruction_set: InstructionSet) -> String {
+        let registers: String = self.registers.iter().map(|(register, value)| format!(" V{:X}={:02x}", register, value)).collect();
+        format!("{:>10}  {:04x}  {:04x}  {:<20}  I={:04x} {}", self.step, self.pc, self.opcode, self.mnemonic(instruction_set), self.index, registers)
+            .trim_end().to_string()
+    }
+
+    fn size(&self, instruction_set: InstructionSet) -> u16 {
+        opcodes::decode(self.opcode, instruction_set).map_or(2, |info| info.size())
+    }
+}
+
+/// Receives the entries of a trace
+pub trait TraceSink {
+    fn write_entry(&mut self, entry: &TraceEntry) -> io::Result<()>;
+
+    /// Completes the output once no more entries will be written
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes every entry as a line of text, see [`TraceEntry::format`]
+pub struct TextTraceSink {
+    writer: BufWriter<File>,
+    instruction_set: InstructionSet,
+}
+
+impl TextTraceSink {
+    pub fn create(path: &Path, instruction_set: InstructionSet) -> io::Result<TextTraceSink> {
+        Ok(TextTraceSink { writer: BufWriter::new(File::create(path)?), instruction_set })
+    }
+}
+
+impl TraceSink for TextTraceSink {
+    fn write_entry(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        writeln!(self.writer, "{}", entry.format(self.instruction_set))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// What each entry of a binary trace leaves out because it follows from the entry before
+#[derive(Debug, Clone, Copy)]
+struct Previous {
+    step: Option<u64>,
+    // The address right after the previous instruction
+    next_pc: Option<u16>,
+    index: u16,
+}
+
+impl Previous {
+    fn new() -> Previous {
+        Previous { step: None, next_pc: None, index: 0 }
+    }
+
+    fn update(&mut self, entry: &TraceEntry, instruction_set: InstructionSet) {
+        self.step = Some(entry.step);
+        self.next_pc = Some(entry.pc.wrapping_add(entry.size(instruction_set)));
+        self.index = entry.index;
+    }
+}
+
+/// Writes entries in a compact binary format, about 4 bytes per instruction, for traces of millions of instructions.
+/// They are read back with [`TraceReader`].
+///
+/// After a header of the magic `RCTR`, the version and the instruction set, each entry starts with a byte of flags,
+/// followed by the opcode and the fields the flags say are stored, in this order:
+///
+/// - The step, as the number of instructions skipped since the previous entry in LEB128, unless there are none
+/// - The pc, unless it is right after the previous instruction
+/// - The word following 4 byte instructions
+/// - The index register, unless it didn't change since the previous entry
+/// - A mask of the registers that changed, followed by their values in order, if any did
+///
+/// Words are stored little endian.
+pub struct BinaryTraceSink {
+    writer: BufWriter<File>,
+    instruction_set: InstructionSet,
+    previous: Previous,
+}
+
+impl BinaryTraceSink {
+    pub fn create(path: &Path, instruction_set: InstructionSet) -> io::Result<BinaryTraceSink> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&TRACE_VERSION.to_le_bytes())?;
+        writer.write_all(&[instruction_set as u8])?;
+        Ok(BinaryTraceSink { writer, instruction_set, previous: Previous::new() })
+    }
+}
+
+impl TraceSink for BinaryTraceSink {
+    fn write_entry(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        let skipped = match self.previous.step {
+            Some(step) => entry.step.wrapping_sub(step).wrapping_sub(1),
+            None => entry.step,
+        };
+        let mut flags = 0;
+        let mut fields = Vec::new();
+        if skipped != 0 {
+            flags |= STORES_STEP;
+            write_leb128(&mut fields, skipped);
+        }
+        if self.previous.next_pc != Some(entry.pc) {
+            flags |= STORES_PC;
+            fields.extend_from_slice(&entry.pc.to_le_bytes());
+        }
+        if entry.size(self.instruction_set) == 4 {
+            flags |= STORES_NEXT_WORD;
+            fields.extend_from_slice(&entry.next_word.to_le_bytes());
+        }
+        if entry.index != self.previous.index {
+            flags |= STORES_INDEX;
+            fields.extend_from_slice(&entry.index.to_le_bytes());
+        }
+        if !entry.registers.is_empty() {
+            flags |= STORES_REGISTERS;
+            let mask = entry.registers.iter().fold(0u16, |mask, (register, _)| mask | 1 << register);
+            fields.extend_from_slice(&mask.to_le_bytes());
+            fields.extend(entry.registers.iter().map(|(_, value)| value));
+        }
+        self.writer.write_all(&[flags])?;
+        self.writer.write_all(&entry.opcode.to_le_bytes())?;
+        self.writer.write_all(&fields)?;
+        self.previous.update(entry, self.instruction_set);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Creates the sink for the file's extension, a [`TextTraceSink`] for `.txt` and `.log` and a [`BinaryTraceSink`] for
+/// anything else
+pub fn create_trace_sink(path: &Path, instruction_set: InstructionSet) -> io::Result<Box<dyn TraceSink>> {
+    let text = path.extension().is_some_and(|extension| {
+        extension.eq_ignore_ascii_case("txt") || extension.eq_ignore_ascii_case("log")
+    });
+    Ok(match text {
+        true => Box::new(TextTraceSink::create(path, instruction_set)?),
+        false => Box::new(BinaryTraceSink::create(path, instruction_set)?),
+    })
+}
+
+/// Reads the entries of a trace written by a [`BinaryTraceSink`], one at a time
+pub struct TraceReader<R: Read> {
+    reader: R,
+    instruction_set: InstructionSet,
+    previous: Previous,
+}
+
+impl TraceReader<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self, TraceError> {
+        TraceReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> TraceReader<R> {
+    /// Reads the header of the trace
+    pub fn new(mut reader: R) -> Result<Self, TraceError> {
+        let mut header = [0; 7];
+        // Data too short for the header isn't a trace either
+        match reader.read_exact(&mut header) {
+            Ok(()) if header[..4] == MAGIC => {}
+            Err(e) if e.kind() != io::ErrorKind::UnexpectedEof => return Err(e.into()),
+            _ => return Err(TraceError::InvalidMagic),
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != TRACE_VERSION {
+            return Err(TraceError::UnsupportedVersion(version));
+        }
+        let instruction_set = match header[6] {
+            0 => InstructionSet::Chip8,
+            1 => InstructionSet::SuperChip,
+            2 => InstructionSet::XoChip,
+            _ => return Err(TraceError::InvalidData("instruction set")),
+        };
+        Ok(TraceReader { reader, instruction_set, previous: Previous::new() })
+    }
+
+    /// The instruction set of the traced program, to disassemble the entries with
+    pub fn instruction_set(&self) -> InstructionSet {
+        self.instruction_set
+    }
+
+    fn read_entry(&mut self, flags: u8) -> Result<TraceEntry, TraceError> {
+        if flags & !(STORES_STEP | STORES_PC | STORES_NEXT_WORD | STORES_INDEX | STORES_REGISTERS) != 0 {
+            return Err(TraceError::InvalidData("flags"));
+        }
+        let opcode = self.read_u16()?;
+        let skipped = if flags & STORES_STEP != 0 { self.read_leb128()? } else { 0 };
+        let step = match self.previous.step {
+            Some(step) => step.wrapping_add(skipped).wrapping_add(1),
+            None => skipped,
+        };
+        let pc = match (flags & STORES_PC != 0, self.previous.next_pc) {
+            (false, Some(pc)) => pc,
+            (false, None) => return Err(TraceError::InvalidData("pc")),
+            (true, _) => self.read_u16()?,
+        };
+        let next_word = if flags & STORES_NEXT_WORD != 0 { self.read_u16()? } else { 0 };
+        let index = if flags & STORES_INDEX != 0 { self.read_u16()? } else { self.previous.index };
+        let mut registers = Vec::new();
+        if flags & STORES_REGISTERS != 0 {
+            let mask = self.read_u16()?;
+            for register in (0..16).filter(|register| mask & 1 << register != 0) {
+                registers.push((register, self.read_u8()?));
+            }
+        }
+        let entry = TraceEntry { step, pc, opcode, next_word, index, registers };
+        self.previous.update(&entry, self.instruction_set);
+        Ok(entry)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, TraceError> {
+        let mut byte = [0];
+        self.reader.read_exact(&mut byte).map_err(truncated)?;
+        Ok(byte[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, TraceError> {
+        let mut bytes = [0; 2];
+        self.reader.read_exact(&mut bytes).map_err(truncated)?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    fn read_leb128(&mut self) -> Result<u64, TraceError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_u8()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(TraceError::InvalidData("step"))
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = Result<TraceEntry, TraceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The trace may only end between entries
+        let mut flags = [0];
+        match self.reader.read(&mut flags) {
+            Ok(0) => None,
+            Ok(_) => Some(self.read_entry(flags[0])),
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+// The state of the emulator before an instruction, to tell what it changed
+#[derive(Debug, Clone, Copy)]
+struct Before {
+    steps: u64,
+    pc: u16,
+    opcode: u16,
+    next_word: u16,
+    registers: [u8; 16],
+}
+
+/// Writes the instructions an emulator runs to a [`TraceSink`], as they pass a [`TraceFilter`].
+/// [`Tracer::before_step`] and [`Tracer::after_step`] are called around every step of the emulator, and tell whether an
+/// instruction ran by the emulator's count of steps, so steps the debugger holds back aren't traced.
+pub struct Tracer {
+    sink: Box<dyn TraceSink>,
+    filter: TraceFilter,
+    before: Option<Before>,
+    traced: u64,
+}
+
+impl Tracer {
+    pub fn new(sink: Box<dyn TraceSink>, filter: TraceFilter) -> Tracer {
+        Tracer { sink, filter, before: None, traced: 0 }
+    }
+
+    /// Whether the filter's limit of instructions was traced
+    pub fn is_done(&self) -> bool {
+        self.filter.limit.is_some_and(|limit| self.traced >= limit)
+    }
+
+    pub fn before_step(&mut self, emulator: &Emulator) {
+        let pc = emulator.pc();
+        let opcode = emulator.opcode_at(pc);
+        self.before = (!self.is_done() && self.filter.matches(pc, opcode)).then(|| Before {
+            steps: emulator.steps(),
+            pc,
+            opcode,
+            next_word: emulator.opcode_at(pc.wrapping_add(2)),
+            registers: *emulator.registers(),
+        });
+    }
+
+    pub fn after_step(&mut self, emulator: &Emulator) -> io::Result<()> {
+        let before = match self.before.take() {
+            Some(before) if emulator.steps() != before.steps => before,
+            _ => return Ok(()),
+        };
+        let mut entry = TraceEntry {
+            step: before.steps,
+            pc: before.pc,
+            opcode: before.opcode,
+            next_word: 0,
+            index: emulator.index(),
+            registers: (0..16u8).zip(emulator.registers())
+                .filter(|(register, value)| before.registers[*register as usize] != **value)
+                .map(|(register, value)| (register, *value))
+                .collect(),
+        };
+        if entry.size(emulator.instruction_set()) == 4 {
+            entry.next_word = before.next_word;
+        }
+        self.traced += 1;
+        self.sink.write_entry(&entry)
+    }
+
+    /// Completes the output. Must be called once tracing is over
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.sink.finish()
+    }
+}
+
+fn write_leb128(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn truncated(error: io::Error) -> TraceError {
+    match error.kind() {
+        io::ErrorKind::UnexpectedEof => TraceError::Truncated,
+        _ => TraceError::Io(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use super::*;
+
+    // Loads a long index, counts in V0 and V1, draws, calls a subroutine that sets a random register and loops
+    const PROGRAM: [u8; 24] = [
+        0xF0, 0x00, 0x03, 0x00, 0x70, 0x01, 0x71, 0x03, 0xD0, 0x15, 0x22, 0x10, 0x8F, 0x10, 0x12, 0x04,
+        0xC2, 0xFF, 0xA2, 0x14, 0x00, 0xEE, 0x00, 0x00,
+    ];
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rustychip-{}-{}", std::process::id(), name))
+    }
+
+    // Traces some steps of the program to the file, returning what was written
+    fn trace(name: &str, filter: TraceFilter) -> Vec<u8> {
+        let path = path(name);
+        let mut emulator = Emulator::builder(&PROGRAM).instruction_set(InstructionSet::XoChip).seed(3).build().unwrap();
+        let mut tracer = Tracer::new(create_trace_sink(&path, emulator.instruction_set()).unwrap(), filter);
+        for step in 0..2000 {
+            if step % 10 == 0 {
+                emulator.tick_clock();
+            }
+            tracer.before_step(&emulator);
+            emulator.step().unwrap();
+            tracer.after_step(&emulator).unwrap();
+        }
+        tracer.finish().unwrap();
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        data
+    }
+
+    fn dump(data: &[u8]) -> String {
+        let reader = TraceReader::new(data).unwrap();
+        let instruction_set = reader.instruction_set();
+        reader.map(|entry| entry.unwrap().format(instruction_set) + "\n").collect()
+    }
+
+    #[test]
+    fn binary_trace_prints_like_the_text_trace() {
+        let text = String::from_utf8(trace("full.txt", TraceFilter::new())).unwrap();
+        let binary = trace("full.trace", TraceFilter::new());
+        assert_eq!(text.lines().count(), 2000);
+        assert!(text.contains("LD I, 0x300"));
+        assert_eq!(dump(&binary), text);
+    }
+
+    #[test]
+    fn filtered_binary_trace_prints_like_the_text_trace() {
+        let filter = || TraceFilter::new().with_range(0x204..=0x20F).with_class(OpcodeClass::Arithmetic).with_limit(300);
+        let text = String::from_utf8(trace("filtered.txt", filter())).unwrap();
+        assert_eq!(text.lines().count(), 300);
+        assert_eq!(dump(&trace("filtered.trace", filter())), text);
+    }
+
+    #[test]
+    fn reader_rejects_invalid_data() {
+        let binary = trace("invalid.trace", TraceFilter::new().with_limit(10));
+        assert!(matches!(TraceReader::new(&b""[..]), Err(TraceError::InvalidMagic)));
+        assert!(matches!(TraceReader::new(&b"RCSS\x01\x00\x00"[..]), Err(TraceError::InvalidMagic)));
+
+        let mut newer = binary.clone();
+        newer[4..6].copy_from_slice(&(TRACE_VERSION + 1).to_le_bytes());
+        assert!(matches!(TraceReader::new(&newer[..]), Err(TraceError::UnsupportedVersion(_))));
+
+        let truncated = TraceReader::new(&binary[..binary.len() - 1]).unwrap().last().unwrap();
+        assert!(matches!(truncated, Err(TraceError::Truncated)));
+    }
+
+    #[test]
+    fn opcode_classes() {
+        assert_eq!(OpcodeClass::of(0x00E0), OpcodeClass::Display);
+        assert_eq!(OpcodeClass::of(0x00EE), OpcodeClass::Flow);
+        assert_eq!(OpcodeClass::of(0x5120), OpcodeClass::Flow);
+        assert_eq!(OpcodeClass::of(0x5122), OpcodeClass::Memory);
+        assert_eq!(OpcodeClass::of(0xC2FF), OpcodeClass::Arithmetic);
+        assert_eq!(OpcodeClass::of(0xF00A), OpcodeClass::Input);
+        assert_eq!(OpcodeClass::of(0xF015), OpcodeClass::Timer);
+        assert_eq!(OpcodeClass::of(0xF033), OpcodeClass::Memory);
+        assert_eq!("Display".parse::<OpcodeClass>(), Ok(OpcodeClass::Display));
+        assert!("sound".parse::<OpcodeClass>().is_err());
+    }
+}
@@ -0,0 +1,330 @@
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+use thiserror::Error;
+use crate::emulator::{Emulator, InstructionSet};
+use crate::trace::TraceEntry;
+
+
+// The fields of a state, in the order of the canonical format, with the number of hex digits of their values
+const FIELDS: [(&str, usize); 22] = [
+    ("pc", 4), ("op", 4), ("i", 4),
+    ("v0", 2), ("v1", 2), ("v2", 2), ("v3", 2), ("v4", 2), ("v5", 2), ("v6", 2), ("v7", 2),
+    ("v8", 2), ("v9", 2), ("va", 2), ("vb", 2), ("vc", 2), ("vd", 2), ("ve", 2), ("vf", 2),
+    ("sp", 2), ("dt", 2), ("st", 2),
+];
+const PC: usize = 0;
+const OPCODE: usize = 1;
+const V0: usize = 3;
+
+#[derive(Error, Debug)]
+pub enum TraceDiffError {
+    #[error("Line {line} of the reference trace is invalid: {message}")]
+    InvalidLine { line: usize, message: String },
+    #[error("Error accessing a trace file: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// The state of the machine before an instruction runs, as a line of a state trace. Lines are made of fields such as
+/// `pc=0200 op=00e0 i=0000 v0=00 ... vf=00 sp=00 dt=00 st=00` separated by spaces, holding the program counter, the
+/// opcode at it, the index register, the registers, the depth of the stack and the timers, in hex. The canonical format
+/// has every field in this order, while states of other emulators may leave fields out, which then aren't compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MachineState {
+    values: [Option<u16>; FIELDS.len()],
+}
+
+impl MachineState {
+    /// The current state of the emulator, with every field
+    pub fn of(emulator: &Emulator) -> MachineState {
+        let mut values = [None; FIELDS.len()];
+        values[PC] = Some(emulator.pc());
+        values[OPCODE] = Some(emulator.opcode_at(emulator.pc()));
+        values[2] = Some(emulator.index());
+        for (value, register) in values[V0..V0 + 16].iter_mut().zip(emulator.registers()) {
+            *value = Some(*register as u16);
+        }
+        values[V0 + 16] = Some(emulator.stack().len() as u16);
+        values[V0 + 17] = Some(emulator.delay_timer as u16);
+        values[V0 + 18] = Some(emulator.sound_timer as u16);
+        MachineState { values }
+    }
+
+    /// Reads a state from a line. Field names are case insensitive, values may start with `0x`, and fields this format
+    /// doesn't have are ignored
+    pub fn parse(line: &str) -> Result<MachineState, String> {
+        let mut values = [None; FIELDS.len()];
+        for field in line.split_whitespace() {
+            let (name, value) = field.split_once('=').ok_or_else(|| format!("Expected a field such as `pc=0200`, got `{}`", field))?;
+            let field = match FIELDS.iter().position(|(known, _)| known.eq_ignore_ascii_case(name)) {
+                Some(field) => field,
+                None => continue,
+            };
+            let digits = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")).unwrap_or(value);
+            let value = u16::from_str_radix(digits, 16).map_err(|e| format!("Invalid value `{}` of {}: {}", value, name, e))?;
+            if FIELDS[field].1 == 2 && value > 0xFF {
+                return Err(format!("The value {} of {} does not fit in a byte", value, name));
+            }
+            values[field] = Some(value);
+        }
+        Ok(MachineState { values })
+    }
+
+    /// The value of a field, by its name in the format, if the state has it
+    pub fn field(&self, name: &str) -> Option<u16> {
+        FIELDS.iter().position(|(known, _)| known.eq_ignore_ascii_case(name)).and_then(|field| self.values[field])
+    }
+
+    /// The state as a line, with the fields it has
+    pub fn format(&self) -> String {
+        let fields: Vec<String> = FIELDS.iter().zip(self.values).filter_map(|((name, digits), value)| {
+            value.map(|value| format!("{}={:02$x}", name, value, digits))
+        }).collect();
+        fields.join(" ")
+    }
+
+    /// Whether a field both states have differs
+    pub fn differs(&self, other: &MachineState) -> bool {
+        (0..FIELDS.len()).any(|field| self.differs_in(other, field))
+    }
+
+    fn differs_in(&self, other: &MachineState, field: usize) -> bool {
+        matches!((self.values[field], other.values[field]), (Some(a), Some(b)) if a != b)
+    }
+}
+
+/// The first state where the emulator and a reference trace disagree
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    /// The number of instructions that ran before the state
+    pub instructions: u64,
+    /// The line of the state in the reference trace, counting from 1
+    pub line: usize,
+    /// The state before the last instruction that ran, which both agreed on. `None` if they disagree from the start
+    pub previous: Option<MachineState>,
+    /// The word following the opcode of the last instruction that ran, the address of an XO-CHIP `F000 NNNN`
+    pub next_word: u16,
+    pub ours: MachineState,
+    pub reference: MachineState,
+}
+
+impl Divergence {
+    /// Describes the instruction that diverged, and shows the state before it and the states after it side by side,
+    /// marking the fields that differ
+    pub fn report(&self, instruction_set: InstructionSet) -> String {
+        let mut report = String::new();
+        match self.previous.and_then(|previous| Some((previous.values[PC]?, previous.values[OPCODE]?))) {
+            Some((pc, opcode)) => {
+                let entry = TraceEntry {
+                    step: self.instructions - 1, pc, opcode, next_word: self.next_word, index: 0, registers: Vec::new(),
+                };
+                let _ = writeln!(report, "The states diverge at line {} of the reference, after instruction {}:", self.line, self.instructions);
+                let _ = writeln!(report, "  {:04x}  {:04x}  {}", pc, opcode, entry.mnemonic(instruction_set));
+            }
+            None => {
+                let _ = writeln!(report, "The states differ before the first instruction, line {} of the reference:", self.line);
+            }
+        }
+        let _ = writeln!(report);
+        let _ = writeln!(report, "      {:<10} {:<10} reference", "before", "rustychip");
+        let show = |state: Option<&MachineState>, field: usize| match state.and_then(|state| state.values[field]) {
+            Some(value) => format!("{:01$x}", value, FIELDS[field].1),
+            None => "-".to_string(),
+        };
+        for (field, (name, _)) in FIELDS.iter().enumerate() {
+            let marker = if self.ours.differs_in(&self.reference, field) { "<<" } else { "" };
+            let line = format!("  {:<3} {:<10} {:<10} {:<10}  {}", name, show(self.previous.as_ref(), field),
+                show(Some(&self.ours), field), show(Some(&self.reference), field), marker);
+            let _ = writeln!(report, "{}", line.trim_end());
+        }
+        report
+    }
+}
+
+/// How a run compared with a reference trace
+#[derive(Debug, Clone)]
+pub enum TraceDiff {
+    /// Every state of the reference matched, after the given number of instructions
+    Matched { instructions: u64 },
+    Diverged(Box<Divergence>),
+    /// The program exited or failed before the end of the reference trace
+    Stopped { instructions: u64, line: usize, reason: String },
+}
+
+/// Runs the emulator along a reference trace of another emulator, comparing the state before every instruction with
+/// the next state of the reference until they differ. Empty lines and lines starting with `#` are skipped. The timers
+/// tick after every `instructions_per_frame` instructions, or as the COSMAC VIP timing model has them, and no keys are
+/// pressed.
+///
+/// Emulators generate different random numbers, so the register set by a `CXNN` instruction is taken from the
+/// reference's next state if it has it. The state of every instruction compared is written to `output` in the
+/// canonical format, if given.
+pub fn diff_trace(emulator: &mut Emulator, instructions_per_frame: usize, reference: impl BufRead, mut output: Option<&mut dyn Write>)
+    -> Result<TraceDiff, TraceDiffError> {
+    let mut previous: Option<MachineState> = None;
+    let mut next_word = 0;
+    let mut instructions = 0;
+    let mut frame_instructions = 0;
+    for (number, line) in reference.lines().enumerate() {
+        let line = line?;
+        let text = line.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let reference = MachineState::parse(text).map_err(|message| TraceDiffError::InvalidLine { line: number + 1, message })?;
+
+        // The state of every line but the first follows from running the instruction of the one before
+        if let Some(previous) = previous {
+            let stopped = |reason: String| Ok(TraceDiff::Stopped { instructions, line: number + 1, reason });
+            if emulator.halted() {
+                return stopped("The program exited".to_string());
+            }
+            if emulator.frame_complete(frame_instructions, instructions_per_frame) {
+                emulator.tick_clock();
+                frame_instructions = 0;
+            }
+            if let Err(e) = emulator.step() {
+                return stopped(e.to_string());
+            }
+            frame_instructions += 1;
+            instructions += 1;
+            let opcode = previous.values[OPCODE].unwrap_or_default();
+            if opcode >> 12 == 0xC {
+                let register = (opcode >> 8 & 0xF) as usize;
+                if let Some(value) = reference.values[V0 + register] {
+                    emulator.set_register(register, value as u8);
+                }
+            }
+        }
+
+        let ours = MachineState::of(emulator);
+        if let Some(output) = output.as_mut() {
+            writeln!(output, "{}", ours.format())?;
+        }
+        if ours.differs(&reference) {
+            let divergence = Divergence { instructions, line: number + 1, previous, next_word, ours, reference };
+            return Ok(TraceDiff::Diverged(Box::new(divergence)));
+        }
+        previous = Some(ours);
+        next_word = emulator.opcode_at(emulator.pc().wrapping_add(2));
+    }
+    Ok(TraceDiff::Matched { instructions })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Loads a long index, then counts in V0 and V1 with a random number in V2 between them
+    const PROGRAM: [u8; 12] = [0xF0, 0x00, 0x03, 0x00, 0x70, 0x01, 0xC2, 0xFF, 0x71, 0x03, 0x12, 0x04];
+
+    fn emulator(seed: u64) -> Emulator {
+        Emulator::builder(&PROGRAM).instruction_set(InstructionSet::XoChip).seed(seed).build().unwrap()
+    }
+
+    // The canonical trace of the given number of states of the program, ticking the timers as the diff does
+    fn reference(seed: u64, states: usize) -> Vec<String> {
+        let mut emulator = emulator(seed);
+        let mut lines = vec![MachineState::of(&emulator).format()];
+        let mut frame_instructions = 0;
+        while lines.len() < states {
+            if emulator.frame_complete(frame_instructions, 10) {
+                emulator.tick_clock();
+                frame_instructions = 0;
+            }
+            emulator.step().unwrap();
+            frame_instructions += 1;
+            lines.push(MachineState::of(&emulator).format());
+        }
+        lines
+    }
+
+    fn diff(seed: u64, reference: &[String]) -> TraceDiff {
+        diff_trace(&mut emulator(seed), 10, reference.join("\n").as_bytes(), None).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        let state = MachineState::parse("PC=0x0204 i=300 v2=0Xff unknown=12 sp=0").unwrap();
+        assert_eq!(state.field("pc"), Some(0x204));
+        assert_eq!(state.field("I"), Some(0x300));
+        assert_eq!(state.field("v2"), Some(0xFF));
+        assert_eq!(state.field("sp"), Some(0));
+        assert_eq!(state.field("op"), None);
+        assert_eq!(state.format(), "pc=0204 i=0300 v2=ff sp=00");
+
+        // Fields missing from either state aren't compared
+        assert!(!state.differs(&MachineState::parse("pc=0204 op=7001").unwrap()));
+        assert!(state.differs(&MachineState::parse("pc=0206").unwrap()));
+
+        assert!(MachineState::parse("pc=0204 v0").is_err());
+        assert!(MachineState::parse("pc=020g").is_err());
+        assert!(MachineState::parse("v0=100").is_err());
+
+        let emulator = emulator(1);
+        let state = MachineState::of(&emulator);
+        assert_eq!(MachineState::parse(&state.format()).unwrap(), state);
+        assert!(state.format().starts_with("pc=0200 op=f000 i=0000 v0=00"));
+    }
+
+    #[test]
+    fn matched() {
+        let reference = reference(1, 300);
+        let mut commented = reference.clone();
+        commented.insert(5, "# A comment".to_string());
+        commented.insert(9, String::new());
+        assert!(matches!(diff(1, &commented), TraceDiff::Matched { instructions: 299 }));
+
+        // The diff writes the same canonical trace as it compares with
+        let mut output = Vec::new();
+        diff_trace(&mut emulator(1), 10, reference.join("\n").as_bytes(), Some(&mut output)).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), reference.join("\n") + "\n");
+    }
+
+    #[test]
+    fn random_numbers_come_from_reference() {
+        let reference = reference(1, 300);
+        assert!(matches!(diff(2, &reference), TraceDiff::Matched { instructions: 299 }));
+    }
+
+    #[test]
+    fn divergence() {
+        let mut reference = reference(1, 300);
+        reference.insert(0, "# Reference".to_string());
+        // The state after 100 instructions, on line 102 with the comment
+        reference[101] = reference[101].replace("st=00", "st=01");
+        let divergence = match diff(1, &reference) {
+            TraceDiff::Diverged(divergence) => divergence,
+            diff => panic!("{:?}", diff),
+        };
+        assert_eq!(divergence.instructions, 100);
+        assert_eq!(divergence.line, 102);
+        assert_eq!(divergence.previous.unwrap().format(), reference[100]);
+        assert_eq!(divergence.ours.format(), reference[101].replace("st=01", "st=00"));
+        let report = divergence.report(InstructionSet::XoChip);
+        assert!(report.starts_with("The states diverge at line 102 of the reference, after instruction 100:\n"));
+        assert!(report.contains("\n  st  00         00         01          <<\n"));
+    }
+
+    #[test]
+    fn divergence_after_long_instruction() {
+        let mut reference = reference(1, 2);
+        reference[1] = reference[1].replace("i=0300", "i=0301");
+        let divergence = match diff(1, &reference) {
+            TraceDiff::Diverged(divergence) => divergence,
+            diff => panic!("{:?}", diff),
+        };
+        assert_eq!((divergence.instructions, divergence.line, divergence.next_word), (1, 2, 0x0300));
+        let report = divergence.report(InstructionSet::XoChip);
+        let entry = TraceEntry { step: 0, pc: 0x200, opcode: 0xF000, next_word: 0x0300, index: 0, registers: Vec::new() };
+        assert!(report.contains(&format!("  0200  f000  {}\n", entry.mnemonic(InstructionSet::XoChip))));
+        assert!(entry.mnemonic(InstructionSet::XoChip).contains("300"));
+    }
+
+    #[test]
+    fn invalid_line() {
+        let reference = ["pc=0200".to_string(), "pc=0204 v0=xyz".to_string()];
+        let result = diff_trace(&mut emulator(1), 10, reference.join("\n").as_bytes(), None);
+        assert!(matches!(result, Err(TraceDiffError::InvalidLine { line: 2, .. })));
+    }
+}
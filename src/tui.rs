@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+use crossterm::{cursor, event, execute, queue, style, terminal};
+use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags};
+use crossterm::style::Color;
+use crate::audio::Sound;
+use crate::display::Frame;
+use crate::emulator::Emulator;
+use crate::frontend::{Frontend, FrontendEvent};
+use crate::memory_view::{Highlight, MemoryRow};
+
+// Most terminals only report key presses, and repeat them while a key is held after a delay. A key counts as held
+// until no press or repeat comes for this long, which is longer after the first press to cover the repeat delay
+const FIRST_PRESS_TIMEOUT: Duration = Duration::from_millis(500);
+const REPEAT_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Width of the side panel, including the gap separating it from the display
+const PANEL_WIDTH: u16 = 24;
+
+// The SDL names of the keys other than characters and function keys
+const KEY_NAMES: [(KeyCode, &str); 15] = [
+    (KeyCode::Char(' '), "Space"), (KeyCode::Esc, "Escape"), (KeyCode::Tab, "Tab"), (KeyCode::Enter, "Return"),
+    (KeyCode::Backspace, "Backspace"), (KeyCode::Up, "Up"), (KeyCode::Down, "Down"), (KeyCode::Left, "Left"),
+    (KeyCode::Right, "Right"), (KeyCode::Home, "Home"), (KeyCode::End, "End"), (KeyCode::PageUp, "PageUp"),
+    (KeyCode::PageDown, "PageDown"), (KeyCode::Insert, "Insert"), (KeyCode::Delete, "Delete"),
+];
+
+/// A [`Frontend`] in the terminal, for when no window can be opened, such as over SSH. Two rows of pixels are drawn in
+/// each line with half block characters, with the registers, the last message and the keys in a panel on the side, and
+/// the memory view below as far as the terminal is high.
+///
+/// Keys are read in raw mode and reported by their SDL names, so letters, digits, `Space`, `Return`, `Tab`, the arrows
+/// and `F1` to `F12` can be bound in key maps. Terminals that report key releases release keys when they are, other
+/// terminals once the key stops repeating. The terminal is restored when the frontend is dropped.
+pub struct Tui {
+    stdout: io::Stdout,
+    key_releases: bool,
+    // When each key pressed without a release event is released, unless it repeats first
+    release_at: HashMap<String, Instant>,
+    bell: bool,
+    sound: Sound,
+    message: Option<String>,
+    // The frame last drawn, to only draw it again when it changed
+    drawn: Option<Frame>,
+    memory: Option<Vec<MemoryRow>>,
+    // Whether the memory view is on the screen, to clear it once it is closed
+    memory_drawn: bool,
+}
+
+impl Tui {
+    /// Switches the terminal to raw mode on an alternate screen
+    pub fn new() -> io::Result<Tui> {
+        terminal::enable_raw_mode()?;
+        // Dropping the frontend from here on restores the terminal, even if the setup fails
+        let mut tui = Tui {
+            stdout: io::stdout(),
+            key_releases: false,
+            release_at: HashMap::new(),
+            bell: false,
+            sound: Sound::Silent,
+            message: None,
+            drawn: None,
+            memory: None,
+            memory_drawn: false,
+        };
+        execute!(tui.stdout, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All))?;
+        if terminal::supports_keyboard_enhancement().unwrap_or(false) {
+            execute!(tui.stdout, event::PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+            tui.key_releases = true;
+        }
+        Ok(tui)
+    }
+
+    /// Rings the terminal bell whenever a sound starts
+    pub fn with_bell(mut self) -> Tui {
+        self.bell = true;
+        self
+    }
+
+    // The SDL name of a key, as used by key maps
+    fn key_name(code: KeyCode) -> Option<String> {
+        match code {
+            KeyCode::Char(c) if c != ' ' => Some(c.to_uppercase().to_string()),
+            KeyCode::F(n) => Some(format!("F{}", n)),
+            code => KEY_NAMES.iter().find(|(named, _)| *named == code).map(|(_, name)| name.to_string()),
+        }
+    }
+
+    fn read_events(&mut self, events: &mut Vec<FrontendEvent>) -> io::Result<()> {
+        while event::poll(Duration::ZERO)? {
+            let key = match event::read()? {
+                Event::Key(key) => key,
+                Event::Resize(..) => {
+                    self.drawn = None;
+                    events.push(FrontendEvent::Redraw);
+                    continue;
+                }
+                _ => continue,
+            };
+            if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                events.push(FrontendEvent::Quit);
+                continue;
+            }
+            let name = match Self::key_name(key.code) {
+                Some(name) => name,
+                None => continue,
+            };
+            if self.key_releases {
+                events.push(match key.kind {
+                    KeyEventKind::Release => FrontendEvent::KeyUp { key: name },
+                    kind => FrontendEvent::KeyDown { key: name, repeat: kind == KeyEventKind::Repeat },
+                });
+            } else {
+                // Repeats only come while the key is held, so the key is released once they stop
+                let repeat = self.release_at.contains_key(&name);
+                let timeout = if repeat { REPEAT_TIMEOUT } else { FIRST_PRESS_TIMEOUT };
+                self.release_at.insert(name.clone(), Instant::now() + timeout);
+                events.push(FrontendEvent::KeyDown { key: name, repeat });
+            }
+        }
+        Ok(())
+    }
+
+    fn color([r, g, b]: [u8; 3]) -> Color {
+        Color::Rgb { r, g, b }
+    }
+
+    fn draw(&mut self, emulator: &Emulator, frame: &Frame) -> io::Result<()> {
+        let draw_display = match &self.drawn {
+            Some(drawn) if (drawn.width, drawn.height) == (frame.width, frame.height) => drawn != frame,
+            // The display is smaller in low resolution, so what was drawn outside it is cleared
+            _ => {
+                queue!(self.stdout, terminal::Clear(terminal::ClearType::All))?;
+                true
+            }
+        };
+        if draw_display {
+            self.drawn = Some(frame.clone());
+        }
+        // Each line shows two rows of pixels, the top one in the foreground colour of the upper half block and the
+        // bottom one in the background colour
+        let rows: Vec<&[[u8; 3]]> = frame.pixels.chunks(frame.width).collect();
+        for (line, rows) in rows.chunks(2).enumerate().filter(|_| draw_display) {
+            queue!(self.stdout, cursor::MoveTo(0, line as u16))?;
+            // Colours are only sent when they change, which keeps the output small
+            let mut colors = None;
+            for (top, bottom) in rows[0].iter().zip(rows[rows.len() - 1]) {
+                if colors != Some((top, bottom)) {
+                    colors = Some((top, bottom));
+                    queue!(self.stdout, style::SetForegroundColor(Self::color(*top)), style::SetBackgroundColor(Self::color(*bottom)))?;
+                }
+                queue!(self.stdout, style::Print('▀'))?;
+            }
+        }
+        queue!(self.stdout, style::ResetColor)?;
+
+        for (row, text) in self.panel(emulator).iter().enumerate() {
+            queue!(self.stdout, cursor::MoveTo(frame.width as u16 + 2, row as u16), style::Print(format!("{:<1$}", text, PANEL_WIDTH as usize)))?;
+        }
+        self.draw_memory(frame.height.div_ceil(2) as u16 + 1)?;
+        self.stdout.flush()
+    }
+
+    // Draws the memory view from the given line down, with a legend of the highlights below it
+    fn draw_memory(&mut self, top: u16) -> io::Result<()> {
+        let rows = match &self.memory {
+            Some(rows) => rows,
+            None if self.memory_drawn => {
+                self.memory_drawn = false;
+                return queue!(self.stdout, cursor::MoveTo(0, top), terminal::Clear(terminal::ClearType::FromCursorDown));
+            }
+            None => return Ok(()),
+        };
+        self.memory_drawn = true;
+        let (_, height) = terminal::size()?;
+        for (line, row) in (top..height).zip(rows) {
+            queue!(self.stdout, cursor::MoveTo(0, line), style::Print(format!("{:04x} ", row.address)))?;
+            for (value, highlight) in &row.bytes {
+                queue!(self.stdout, style::Print(' '))?;
+                Self::queue_highlighted(&mut self.stdout, *highlight, &format!("{:02x}", value))?;
+            }
+        }
+        let legend = top + rows.len() as u16 + 1;
+        if legend < height {
+            queue!(self.stdout, cursor::MoveTo(0, legend))?;
+            for (highlight, name) in [(Highlight::Pc, "pc"), (Highlight::Index, "index"), (Highlight::Written, "written"), (Highlight::Cursor, "cursor")] {
+                Self::queue_highlighted(&mut self.stdout, highlight, name)?;
+                queue!(self.stdout, style::Print(' '))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn queue_highlighted(stdout: &mut io::Stdout, highlight: Highlight, text: &str) -> io::Result<()> {
+        match highlight {
+            Highlight::None => queue!(stdout, style::Print(text)),
+            highlight => {
+                let (color, background) = highlight.colors();
+                queue!(stdout, style::SetForegroundColor(Self::color(color)), style::SetBackgroundColor(Self::color(background)),
+                    style::Print(text), style::ResetColor)
+            }
+        }
+    }
+
+    fn panel(&self, emulator: &Emulator) -> Vec<String> {
+        let mut lines = vec![
+            format!("PC {:#06x}  I {:#06x}", emulator.pc(), emulator.index()),
+            format!("DT {:<3}  ST {:<3}  SP {}", emulator.delay_timer, emulator.sound_timer, emulator.stack().len()),
+            String::new(),
+        ];
+        let registers = emulator.registers();
+        for row in 0..8 {
+            lines.push(format!("V{:X} {:02x}    V{:X} {:02x}", row, registers[row], row + 8, registers[row + 8]));
+        }
+        lines.push(String::new());
+        let message: Vec<char> = self.message.as_deref().unwrap_or_default().chars().collect();
+        // Messages are wrapped over up to three lines, which are cleared when the message gets shorter
+        let mut message_lines: Vec<String> = message.chunks(PANEL_WIDTH as usize - 2).map(|chunk| chunk.iter().collect()).collect();
+        message_lines.resize(message_lines.len().max(3), String::new());
+        lines.extend(message_lines);
+        lines.push(String::new());
+        lines.push("Esc quit   P pause".to_string());
+        lines.push("N next frame".to_string());
+        lines.push("Tab fast forward".to_string());
+        lines.push("- = slower, faster".to_string());
+        lines.push("F5 save    F9 load".to_string());
+        lines.push("F6 F7 save slot".to_string());
+        lines.push("F12 screenshot".to_string());
+        lines.push("F2 memory view".to_string());
+        lines.push("Backspace rewind".to_string());
+        lines
+    }
+}
+
+impl Frontend for Tui {
+    fn present(&mut self, emulator: &Emulator, frame: &Frame) {
+        // The terminal is only written to, so if that fails there is nowhere left to report it
+        let _ = self.draw(emulator, frame);
+    }
+
+    // The terminal has no audio, so the bell rings instead when a sound starts
+    fn play(&mut self, sound: Sound) {
+        if self.bell && self.sound == Sound::Silent && sound != Sound::Silent {
+            let _ = write!(self.stdout, "\x07").and_then(|_| self.stdout.flush());
+        }
+        self.sound = sound;
+    }
+
+    fn poll_events(&mut self) -> Vec<FrontendEvent> {
+        let mut events = Vec::new();
+        if self.read_events(&mut events).is_err() {
+            events.push(FrontendEvent::Quit);
+        }
+        let now = Instant::now();
+        let released: Vec<String> = self.release_at.iter()
+            .filter(|(_, release_at)| **release_at <= now)
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in released {
+            self.release_at.remove(&name);
+            events.push(FrontendEvent::KeyUp { key: name });
+        }
+        events
+    }
+
+    fn is_key(&self, name: &str) -> bool {
+        let mut chars = name.chars();
+        let single = matches!((chars.next(), chars.next()), (Some(c), None) if !c.is_whitespace());
+        let function = name.strip_prefix(['F', 'f']).and_then(|n| n.parse::<u8>().ok()).is_some_and(|n| (1..=12).contains(&n));
+        single || function || KEY_NAMES.iter().any(|(_, named)| named.eq_ignore_ascii_case(name))
+    }
+
+    fn message(&mut self, message: &str) {
+        self.message = Some(message.to_string());
+    }
+
+    fn show_memory(&mut self, rows: Option<&[MemoryRow]>) {
+        self.memory = rows.map(|rows| rows.to_vec());
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        if self.key_releases {
+            let _ = execute!(self.stdout, event::PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.stdout, style::ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use crate::display::{Palette, render_rgb};
+#[cfg(feature = "capture")]
+use crate::display::{capture_size, render_indexed};
+use crate::emulator::Emulator;
+#[cfg(feature = "capture")]
+use crate::scheduler::FRAME_RATE;
+
+
+// GIF frame delays are in hundredths of a second, and most viewers slow down frames shorter than two
+#[cfg(feature = "capture")]
+const MIN_GIF_DELAY: u64 = 2;
+
+/// Receives the display after every emulated frame of a recording
+pub trait VideoSink {
+    /// Writes the next frame, 1/60th of a second after the previous one
+    fn write_frame(&mut self, emulator: &Emulator) -> io::Result<()>;
+
+    /// Completes the output once no more frames will be written
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Records an animated GIF. Frames are only stored when the display changes, and changes less than 2/100ths of a second
+/// apart are merged, as GIFs can't show frames any shorter
+#[cfg(feature = "capture")]
+pub struct GifSink {
+    encoder: Option<gif::Encoder<BufWriter<File>>>,
+    scale: usize,
+    // The frame waiting for the next change to know its length, and when it started in frames
+    pending: Option<(Vec<u8>, u64)>,
+    frame: u64,
+}
+
+#[cfg(feature = "capture")]
+impl GifSink {
+    /// Creates the file, at the size given by [`capture_size`](crate::display::capture_size) for the scale
+    pub fn create(path: &Path, palette: &Palette, scale: usize) -> io::Result<GifSink> {
+        let (width, height) = capture_size(scale);
+        if width > u16::MAX as usize || height > u16::MAX as usize {
+            return Err(io::Error::other(format!("A scale of {} is too large for a GIF", scale)));
+        }
+        let colors = palette.colors.concat();
+        let mut encoder = gif::Encoder::new(BufWriter::new(File::create(path)?), width as u16, height as u16, &colors)
+            .map_err(to_io_error)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(to_io_error)?;
+        Ok(GifSink { encoder: Some(encoder), scale, pending: None, frame: 0 })
+    }
+
+    fn centiseconds(frame: u64) -> u64 {
+        frame * 100 / FRAME_RATE as u64
+    }
+
+    fn write_pending(&mut self, end: u64) -> io::Result<()> {
+        let (encoder, (pixels, start)) = match (&mut self.encoder, self.pending.take()) {
+            (Some(encoder), Some(pending)) => (encoder, pending),
+            _ => return Ok(()),
+        };
+        let (width, height) = capture_size(self.scale);
+        let mut frame = gif::Frame::from_indexed_pixels(width as u16, height as u16, pixels, None);
+        let delay = Self::centiseconds(end).saturating_sub(Self::centiseconds(start)).max(MIN_GIF_DELAY);
+        frame.delay = delay.min(u16::MAX as u64) as u16;
+        encoder.write_frame(&frame).map_err(to_io_error)
+    }
+}
+
+#[cfg(feature = "capture")]
+impl VideoSink for GifSink {
+    fn write_frame(&mut self, emulator: &Emulator) -> io::Result<()> {
+        let pixels = render_indexed(emulator, self.scale);
+        let frame = self.frame;
+        self.frame += 1;
+        match &mut self.pending {
+            Some((pending, _)) if *pending == pixels => Ok(()),
+            // Too soon after the pending frame started, which is replaced instead
+            Some((pending, start)) if Self::centiseconds(frame) - Self::centiseconds(*start) < MIN_GIF_DELAY => {
+                *pending = pixels;
+                Ok(())
+            }
+            _ => {
+                self.write_pending(frame)?;
+                self.pending = Some((pixels, frame));
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_pending(self.frame)?;
+        match self.encoder.take() {
+            Some(encoder) => encoder.into_inner()?.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Writes every frame as raw 24-bit RGB pixels, one after the other with no header, at the size given by
+/// [`capture_size`](crate::display::capture_size). The file can be encoded with ffmpeg, for example at a scale of 10
+/// with `ffmpeg -f rawvideo -pixel_format rgb24 -video_size 640x320 -framerate 60 -i video.rgb video.mp4`
+pub struct RawVideoSink {
+    writer: BufWriter<File>,
+    palette: Palette,
+    scale: usize,
+}
+
+impl RawVideoSink {
+    pub fn create(path: &Path, palette: &Palette, scale: usize) -> io::Result<RawVideoSink> {
+        Ok(RawVideoSink { writer: BufWriter::new(File::create(path)?), palette: *palette, scale })
+    }
+}
+
+impl VideoSink for RawVideoSink {
+    fn write_frame(&mut self, emulator: &Emulator) -> io::Result<()> {
+        self.writer.write_all(&render_rgb(emulator, &self.palette, self.scale))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Creates the sink for the file's extension, a `GifSink` for `.gif` and a [`RawVideoSink`] for anything else. GIFs
+/// need the `capture` feature
+pub fn create_video_sink(path: &Path, palette: &Palette, scale: usize) -> io::Result<Box<dyn VideoSink>> {
+    let gif = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+    Ok(match gif {
+        #[cfg(feature = "capture")]
+        true => Box::new(GifSink::create(path, palette, scale)?),
+        #[cfg(not(feature = "capture"))]
+        true => return Err(io::Error::new(io::ErrorKind::Unsupported, "Recording GIFs needs the capture feature")),
+        false => Box::new(RawVideoSink::create(path, palette, scale)?),
+    })
+}
+
+#[cfg(feature = "capture")]
+fn to_io_error(error: gif::EncodingError) -> io::Error {
+    match error {
+        gif::EncodingError::Io(error) => error,
+        error => io::Error::other(error),
+    }
+}
@@ -0,0 +1,197 @@
+use std::sync::{Arc, Mutex};
+use sdl2::EventPump;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Texture, TextureCreator, WindowCanvas};
+use sdl2::video::{FullscreenType, WindowContext};
+use crate::audio::{AudioConfig, SAMPLE_RATE, Sound, ToneGenerator};
+use crate::display::{Frame, Scaling};
+use crate::emulator::{DISPLAY_HEIGHT, DISPLAY_WIDTH, Emulator};
+use crate::frontend::{Frontend, FrontendEvent};
+use crate::memory_view::{self, MemoryRow};
+
+// Plays the emulator's sound on the SDL audio thread, as last published by the window
+struct Beeper {
+    generator: ToneGenerator,
+    sound: Arc<Mutex<Sound>>,
+}
+
+impl AudioCallback for Beeper {
+    type Channel = f32;
+
+    fn callback(&mut self, samples: &mut [f32]) {
+        let sound = *self.sound.lock().unwrap();
+        self.generator.fill(samples, sound);
+    }
+}
+
+/// A [`Frontend`] showing the display in a resizable SDL window and playing the sound through SDL audio. The display
+/// keeps its aspect ratio, with black bars around it when the window has another shape. The memory view takes the
+/// bottom half of the window while it is open
+pub struct Window {
+    canvas: WindowCanvas,
+    texture_creator: TextureCreator<WindowContext>,
+    event_pump: EventPump,
+    scaling: Scaling,
+    fullscreen: bool,
+    // The memory view drawn as a picture
+    memory: Option<Frame>,
+    // The textures of the display and the memory view, kept from one frame to the next. Without a lifetime, as SDL's
+    // unsafe_textures has them, they can live next to the canvas and are freed along with it
+    display_texture: Option<Texture>,
+    memory_texture: Option<Texture>,
+    sound: Arc<Mutex<Sound>>,
+    // The device stops playing when dropped, so it is kept as long as the window
+    _audio_device: Option<AudioDevice<Beeper>>,
+}
+
+impl Window {
+    /// Opens the window, and an audio device playing the given tone, or the XO-CHIP audio pattern, while the sound
+    /// timer is running. Without a tone, or if no audio device can be opened, the window is silent
+    pub fn new(audio: Option<AudioConfig>) -> Result<Window, String> {
+        let sdl = sdl2::init()?;
+        let video_subsystem = sdl.video()?;
+        let mut window = video_subsystem.window("RustyChip", 640, 320)
+            .position_centered()
+            .resizable()
+            .build()
+            .map_err(|e| e.to_string())?;
+        window.set_minimum_size(DISPLAY_WIDTH as u32, DISPLAY_HEIGHT as u32).map_err(|e| e.to_string())?;
+
+        let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        let texture_creator = canvas.texture_creator();
+
+        let event_pump = sdl.event_pump()?;
+
+        let sound = Arc::new(Mutex::new(Sound::Silent));
+        let audio_device = audio.and_then(|config| match Self::open_audio(&sdl, config, sound.clone()) {
+            Ok(device) => Some(device),
+            Err(e) => {
+                println!("Error opening audio, continuing without sound: {}", e);
+                None
+            }
+        });
+
+        Ok(Window {
+            canvas,
+            texture_creator,
+            event_pump,
+            scaling: Scaling::Fit,
+            fullscreen: false,
+            memory: None,
+            display_texture: None,
+            memory_texture: None,
+            sound,
+            _audio_device: audio_device,
+        })
+    }
+
+    /// Scales the display as given instead of to fit the window
+    pub fn with_scaling(mut self, scaling: Scaling) -> Window {
+        self.scaling = scaling;
+        self
+    }
+
+    /// Starts in full screen, unless that isn't possible
+    pub fn with_fullscreen(mut self) -> Window {
+        self.set_fullscreen(true);
+        self
+    }
+
+    fn set_fullscreen(&mut self, fullscreen: bool) {
+        let fullscreen_type = if fullscreen { FullscreenType::Desktop } else { FullscreenType::Off };
+        match self.canvas.window_mut().set_fullscreen(fullscreen_type) {
+            Ok(()) => self.fullscreen = fullscreen,
+            Err(e) => println!("Error switching full screen: {}", e),
+        }
+    }
+
+    // Draws the frame into a texture with one texel per pixel, which the GPU scales to the given area. The texture is
+    // only created again when the size of the frame changes, such as when switching to high resolution
+    fn copy_frame(canvas: &mut WindowCanvas, texture_creator: &TextureCreator<WindowContext>, texture: &mut Option<Texture>,
+                  frame: &Frame, (x, y, width, height): (u32, u32, u32, u32)) {
+        let size = (frame.width as u32, frame.height as u32);
+        if texture.as_ref().map(|texture| (texture.query().width, texture.query().height)) != Some(size) {
+            if let Some(texture) = texture.take() {
+                // Safe as the canvas the texture belongs to is still alive
+                unsafe { texture.destroy() };
+            }
+            *texture = Some(texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, size.0, size.1).unwrap());
+        }
+        let texture = texture.as_mut().unwrap();
+        texture.with_lock(None, |buffer, pitch| {
+            for (y, row) in frame.pixels.chunks(frame.width).enumerate() {
+                for (x, color) in row.iter().enumerate() {
+                    buffer[y * pitch + x * 3..y * pitch + x * 3 + 3].copy_from_slice(color);
+                }
+            }
+        }).unwrap();
+        canvas.copy(texture, None, Rect::new(x as i32, y as i32, width, height)).unwrap();
+    }
+
+    fn open_audio(sdl: &sdl2::Sdl, config: AudioConfig, sound: Arc<Mutex<Sound>>) -> Result<AudioDevice<Beeper>, String> {
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE as i32),
+            channels: Some(1),
+            samples: None,
+        };
+        let device = sdl.audio()?.open_playback(None, &desired, |spec| Beeper {
+            generator: ToneGenerator::new(config, spec.freq as u32),
+            sound,
+        })?;
+        device.resume();
+        Ok(device)
+    }
+}
+
+impl Frontend for Window {
+    fn present(&mut self, _emulator: &Emulator, frame: &Frame) {
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+        let (window_width, window_height) = self.canvas.output_size().unwrap();
+        let display_height = if self.memory.is_some() { window_height / 2 } else { window_height };
+        let area = self.scaling.place(window_width, display_height);
+        Self::copy_frame(&mut self.canvas, &self.texture_creator, &mut self.display_texture, frame, area);
+        if let Some(memory) = self.memory.take() {
+            // Scaled by a whole number so that the digits stay legible
+            let (width, height) = (memory.width as u32, memory.height as u32);
+            let area_height = window_height - display_height;
+            let scale = (window_width / width).min(area_height / height).max(1);
+            let x = window_width.saturating_sub(width * scale) / 2;
+            let y = display_height + area_height.saturating_sub(height * scale) / 2;
+            let area = (x, y, width * scale, height * scale);
+            Self::copy_frame(&mut self.canvas, &self.texture_creator, &mut self.memory_texture, &memory, area);
+            self.memory = Some(memory);
+        }
+        self.canvas.present();
+    }
+
+    fn play(&mut self, sound: Sound) {
+        *self.sound.lock().unwrap() = sound;
+    }
+
+    fn poll_events(&mut self) -> Vec<FrontendEvent> {
+        self.event_pump.poll_iter().filter_map(|event| match event {
+            Event::Quit {..} => Some(FrontendEvent::Quit),
+            Event::Window { win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed, .. } => Some(FrontendEvent::Redraw),
+            Event::KeyDown { keycode: Some(keycode), repeat, .. } => Some(FrontendEvent::KeyDown { key: keycode.name(), repeat }),
+            Event::KeyUp { keycode: Some(keycode), .. } => Some(FrontendEvent::KeyUp { key: keycode.name() }),
+            _ => None,
+        }).collect()
+    }
+
+    fn is_key(&self, name: &str) -> bool {
+        Keycode::from_name(name).is_some()
+    }
+
+    fn show_memory(&mut self, rows: Option<&[MemoryRow]>) {
+        self.memory = rows.map(memory_view::render_rows);
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        self.set_fullscreen(!self.fullscreen);
+    }
+}